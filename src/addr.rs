@@ -0,0 +1,103 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::ethertype::EtherType;
+
+/// A 48-bit IEEE 802 MAC address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    /// The all-ones broadcast address
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+
+    pub const fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> Self {
+        Self(octets)
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// Accepts six hex octets separated by `:` or `-`, e.g. `02:00:5e:10:00:01`
+impl FromStr for MacAddr {
+    type Err = ParseMacError;
+
+    fn from_str(s: &str) -> Result<Self, ParseMacError> {
+        let sep = if s.contains('-') { '-' } else { ':' };
+        let mut octets = [0; 6];
+        let mut parts = s.split(sep);
+
+        for octet in &mut octets {
+            let part = parts.next().filter(|part| (1..=2).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_hexdigit())).ok_or(ParseMacError)?;
+            *octet = u8::from_str_radix(part, 16).map_err(|_| ParseMacError)?;
+        }
+
+        match parts.next() {
+            Some(_) => Err(ParseMacError),
+            None => Ok(Self(octets)),
+        }
+    }
+}
+
+/// The error from parsing a malformed [`MacAddr`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseMacError;
+
+impl fmt::Display for ParseMacError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid MAC address")
+    }
+}
+
+impl Error for ParseMacError {}
+
+/// A link-layer destination: the interface to send on, the protocol and the destination hardware address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LinkAddr {
+    ifindex: u32,
+    protocol: EtherType,
+    addr: MacAddr,
+}
+
+impl LinkAddr {
+    pub fn new(ifindex: u32, protocol: EtherType, addr: MacAddr) -> Self {
+        Self { ifindex, protocol, addr }
+    }
+
+    pub fn ifindex(&self) -> u32 {
+        self.ifindex
+    }
+
+    pub fn protocol(&self) -> EtherType {
+        self.protocol
+    }
+
+    pub fn addr(&self) -> MacAddr {
+        self.addr
+    }
+
+    pub(crate) fn to_sockaddr(self) -> libc::sockaddr_ll {
+        let mut sll_addr = [0; 8];
+        sll_addr[..6].copy_from_slice(&self.addr.0);
+
+        libc::sockaddr_ll {
+            sll_family: libc::AF_PACKET as u16,
+            sll_protocol: self.protocol.to_network(),
+            sll_ifindex: self.ifindex as i32,
+            sll_hatype: 0,
+            sll_pkttype: 0,
+            sll_halen: 6,
+            sll_addr,
+        }
+    }
+}
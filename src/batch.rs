@@ -0,0 +1,130 @@
+use std::{future::poll_fn, io, os::fd::RawFd};
+
+use crate::{packet, sys, PacketInfo, RawSock, SocketKind};
+
+/// A receive buffer for [`RawSock::read_batch`], holding one frame and its metadata once filled
+#[derive(Debug, Clone)]
+pub struct PacketBuf {
+    buf: Vec<u8>,
+    len: usize,
+    truncated: bool,
+    info: Option<PacketInfo>,
+}
+
+impl PacketBuf {
+    /// A buffer with room for frames of up to `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: vec![0; capacity],
+            len: 0,
+            truncated: false,
+            info: None,
+        }
+    }
+
+    /// The frame received into the buffer, cut to its capacity
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Whether the frame was longer than the buffer and lost its end (`MSG_TRUNC`). Its full
+    /// length is in [`PacketInfo::frame_len`]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The link-layer metadata of the frame, or `None` until one is received
+    pub fn info(&self) -> Option<&PacketInfo> {
+        self.info.as_ref()
+    }
+}
+
+impl RawSock {
+    /// Reads as many frames as are queued, up to one per buffer, with a single `recvmmsg`.
+    /// Returns how many of `bufs`, from the start, were filled; the rest are left as they were.
+    ///
+    /// With a userspace filter set, frames are received one at a time instead, so that the filter
+    /// sees each of them whole
+    pub async fn read_batch(&self, bufs: &mut [PacketBuf]) -> io::Result<usize> {
+        if bufs.is_empty() {
+            return Ok(0);
+        }
+
+        let filled = poll_fn(|cx| self.poll_read(cx, |fd| {
+            if self.filter.read().unwrap().is_some() {
+                self.recv_batch_filtered(fd, bufs)
+            } else {
+                self.recv_batch(fd, bufs)
+            }
+        }))
+        .await;
+
+        filled.map_err(|err| self.counters.error(err))
+    }
+
+    fn recv_batch(&self, fd: RawFd, bufs: &mut [PacketBuf]) -> io::Result<usize> {
+        let mut slices: Vec<_> = bufs.iter_mut().map(|buf| &mut buf.buf[..]).collect();
+        let received = sys::recvmmsg(fd, &mut slices, libc::MSG_TRUNC)?;
+
+        for ((frame_len, meta), buf) in received.iter().zip(bufs.iter_mut()) {
+            let mut info = PacketInfo::from_msg(meta, *frame_len);
+            let len = (*frame_len).min(buf.capacity());
+
+            buf.len = if self.reinsert_vlan { packet::reinsert_vlan(&mut buf.buf, len, &mut info) } else { len };
+            // Not MSG_TRUNC, which misses a frame that only outgrew the buffer with its tag back
+            buf.truncated = info.frame_len() > buf.len;
+            buf.info = Some(info);
+            self.counters.received(buf.len);
+        }
+
+        Ok(received.len())
+    }
+
+    fn recv_batch_filtered(&self, fd: RawFd, bufs: &mut [PacketBuf]) -> io::Result<usize> {
+        let mut filled = 0;
+        for buf in bufs.iter_mut() {
+            let (len, info, truncated) = match self.recv_filtered(fd, &mut buf.buf) {
+                Ok(frame) => frame,
+                // Like recvmmsg, an error after the first frame ends the batch early
+                Err(_) if filled > 0 => break,
+                Err(err) => return Err(err),
+            };
+
+            buf.len = len;
+            buf.truncated = truncated;
+            buf.info = Some(info);
+            self.counters.received(len);
+            filled += 1;
+        }
+
+        Ok(filled)
+    }
+
+    /// Sends each of `frames` with a single `sendmmsg`, returning how many were sent. Sending
+    /// stops at the first frame the kernel refuses, whose error is only returned if it is the
+    /// first one
+    pub async fn write_batch(&self, frames: &[&[u8]]) -> io::Result<usize> {
+        if self.kind == SocketKind::Cooked {
+            return Err(self.counters.error(crate::cooked_write()));
+        }
+        if frames.is_empty() {
+            return Ok(0);
+        }
+
+        let sent = poll_fn(|cx| self.poll_write(cx, |fd| sys::sendmmsg(fd, frames))).await.map_err(|err| self.counters.error(err))?;
+        frames[..sent].iter().for_each(|frame| self.counters.sent(frame.len()));
+        Ok(sent)
+    }
+}
@@ -0,0 +1,275 @@
+//! Classic BPF programs, as used by socket filters and `PACKET_FANOUT_CBPF`.
+//!
+//! Instructions can be written with the [`SockFilter`] constructors instead of hand-assembling
+//! opcodes. Jump offsets are relative to the next instruction, as in the kernel:
+//!
+//! ```
+//! use async_raw::bpf::SockFilter;
+//!
+//! // Accept IPv4 frames whole, drop everything else
+//! let filter = [
+//!     SockFilter::ldh(12),
+//!     SockFilter::jeq(0x0800, 0, 1),
+//!     SockFilter::ret(u32::MAX),
+//!     SockFilter::ret(0),
+//! ];
+//! ```
+//!
+//! Or compiled from a pcap-filter expression, as given to tcpdump, without going through libpcap:
+//!
+//! ```
+//! use async_raw::bpf::Program;
+//!
+//! let program = Program::compile("tcp port 80 and not host 10.0.0.1").unwrap();
+//! println!("{program}"); // The listing `tcpdump -d` would print
+//! ```
+//!
+//! The supported subset is `ether host|src|dst`, `ether proto`, `ether broadcast|multicast`,
+//! `vlan [id]`, `ip`, `ip6`, `arp`, `rarp`, `tcp`, `udp`, `sctp`, `icmp`, `icmp6`, `ip|ip6 proto`,
+//! `[src|dst] host|net` for IPv4 and IPv6 addresses, `[tcp|udp|sctp] [src|dst] port`, `less`,
+//! `greater`, and `and`/`or`/`not` (also `&&`, `||`, `!`) with parentheses. As in tcpdump, `and`
+//! and `or` have equal precedence, and a bare address after them reuses the previous qualifiers
+
+mod codegen;
+mod compile;
+mod interp;
+mod program;
+
+pub use compile::CompileError;
+pub use interp::Ancillary;
+pub use program::Program;
+
+use libc::{BPF_ABS, BPF_ALU, BPF_B, BPF_H, BPF_IMM, BPF_IND, BPF_JMP, BPF_K, BPF_LD, BPF_LDX, BPF_LEN, BPF_MEM, BPF_MISC, BPF_MSH, BPF_RET, BPF_ST, BPF_STX, BPF_W, BPF_X};
+
+/// One classic BPF instruction, laid out like the kernel's `struct sock_filter`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+/// The operations of `BPF_ALU` instructions, applied to A with either a constant or X
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Lsh,
+    Rsh,
+}
+
+impl AluOp {
+    const fn code(self) -> u32 {
+        match self {
+            AluOp::Add => libc::BPF_ADD,
+            AluOp::Sub => libc::BPF_SUB,
+            AluOp::Mul => libc::BPF_MUL,
+            AluOp::Div => libc::BPF_DIV,
+            AluOp::Mod => libc::BPF_MOD,
+            AluOp::And => libc::BPF_AND,
+            AluOp::Or => libc::BPF_OR,
+            AluOp::Xor => libc::BPF_XOR,
+            AluOp::Lsh => libc::BPF_LSH,
+            AluOp::Rsh => libc::BPF_RSH,
+        }
+    }
+}
+
+impl SockFilter {
+    pub const fn new(code: u16, jt: u8, jf: u8, k: u32) -> Self {
+        Self { code, jt, jf, k }
+    }
+
+    const fn stmt(code: u32, k: u32) -> Self {
+        Self::new(code as u16, 0, 0, k)
+    }
+
+    const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> Self {
+        Self::new(code as u16, jt, jf, k)
+    }
+
+    /// `ld [k]`: A = the 32-bit word at offset `k`
+    pub const fn ld(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_W | BPF_ABS, k)
+    }
+
+    /// `ldh [k]`: A = the 16-bit halfword at offset `k`
+    pub const fn ldh(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_H | BPF_ABS, k)
+    }
+
+    /// `ldb [k]`: A = the byte at offset `k`
+    pub const fn ldb(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_B | BPF_ABS, k)
+    }
+
+    /// `ld [x + k]`
+    pub const fn ld_ind(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_W | BPF_IND, k)
+    }
+
+    /// `ldh [x + k]`
+    pub const fn ldh_ind(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_H | BPF_IND, k)
+    }
+
+    /// `ldb [x + k]`
+    pub const fn ldb_ind(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_B | BPF_IND, k)
+    }
+
+    /// `ld #k`: A = `k`
+    pub const fn ld_imm(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_W | BPF_IMM, k)
+    }
+
+    /// `ld #len`: A = the frame length
+    pub const fn ld_len() -> Self {
+        Self::stmt(BPF_LD | BPF_W | BPF_LEN, 0)
+    }
+
+    /// `ld M[k]`: A = scratch memory slot `k`
+    pub const fn ld_mem(k: u32) -> Self {
+        Self::stmt(BPF_LD | BPF_W | BPF_MEM, k)
+    }
+
+    /// A = one of the kernel's ancillary values, e.g. [`libc::SKF_AD_PROTOCOL`]
+    pub const fn ld_ancillary(offset: i32) -> Self {
+        Self::ld((libc::SKF_AD_OFF + offset) as u32)
+    }
+
+    /// `ldx #k`: X = `k`
+    pub const fn ldx_imm(k: u32) -> Self {
+        Self::stmt(BPF_LDX | BPF_W | BPF_IMM, k)
+    }
+
+    /// `ldx #len`: X = the frame length
+    pub const fn ldx_len() -> Self {
+        Self::stmt(BPF_LDX | BPF_W | BPF_LEN, 0)
+    }
+
+    /// `ldx M[k]`: X = scratch memory slot `k`
+    pub const fn ldx_mem(k: u32) -> Self {
+        Self::stmt(BPF_LDX | BPF_W | BPF_MEM, k)
+    }
+
+    /// `ldxb 4*([k]&0xf)`: X = the length of the IPv4 header starting at offset `k`
+    pub const fn ldx_msh(k: u32) -> Self {
+        Self::stmt(BPF_LDX | BPF_B | BPF_MSH, k)
+    }
+
+    /// `st M[k]`: scratch memory slot `k` = A
+    pub const fn st(k: u32) -> Self {
+        Self::stmt(BPF_ST, k)
+    }
+
+    /// `stx M[k]`: scratch memory slot `k` = X
+    pub const fn stx(k: u32) -> Self {
+        Self::stmt(BPF_STX, k)
+    }
+
+    /// A = A `op` `k`
+    pub const fn alu(op: AluOp, k: u32) -> Self {
+        Self::stmt(BPF_ALU | op.code() | BPF_K, k)
+    }
+
+    /// A = A `op` X
+    pub const fn alu_x(op: AluOp) -> Self {
+        Self::stmt(BPF_ALU | op.code() | BPF_X, 0)
+    }
+
+    /// A = -A
+    pub const fn neg() -> Self {
+        Self::stmt(BPF_ALU | libc::BPF_NEG, 0)
+    }
+
+    /// Unconditionally skip `k` instructions
+    pub const fn ja(k: u32) -> Self {
+        Self::stmt(BPF_JMP | libc::BPF_JA, k)
+    }
+
+    /// Skip `jt` instructions if A == `k`, otherwise `jf`
+    pub const fn jeq(k: u32, jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JEQ | BPF_K, k, jt, jf)
+    }
+
+    /// Skip `jt` instructions if A > `k`, otherwise `jf`
+    pub const fn jgt(k: u32, jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JGT | BPF_K, k, jt, jf)
+    }
+
+    /// Skip `jt` instructions if A >= `k`, otherwise `jf`
+    pub const fn jge(k: u32, jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JGE | BPF_K, k, jt, jf)
+    }
+
+    /// Skip `jt` instructions if A & `k` is non-zero, otherwise `jf`
+    pub const fn jset(k: u32, jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JSET | BPF_K, k, jt, jf)
+    }
+
+    /// Skip `jt` instructions if A == X, otherwise `jf`
+    pub const fn jeq_x(jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JEQ | BPF_X, 0, jt, jf)
+    }
+
+    /// Skip `jt` instructions if A > X, otherwise `jf`
+    pub const fn jgt_x(jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JGT | BPF_X, 0, jt, jf)
+    }
+
+    /// Skip `jt` instructions if A >= X, otherwise `jf`
+    pub const fn jge_x(jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JGE | BPF_X, 0, jt, jf)
+    }
+
+    /// Skip `jt` instructions if A & X is non-zero, otherwise `jf`
+    pub const fn jset_x(jt: u8, jf: u8) -> Self {
+        Self::jump(BPF_JMP | libc::BPF_JSET | BPF_X, 0, jt, jf)
+    }
+
+    /// Accept up to `k` bytes of the frame; 0 drops it
+    pub const fn ret(k: u32) -> Self {
+        Self::stmt(BPF_RET | BPF_K, k)
+    }
+
+    /// Accept up to A bytes of the frame; 0 drops it
+    pub const fn ret_a() -> Self {
+        Self::stmt(BPF_RET | libc::BPF_A, 0)
+    }
+
+    /// X = A
+    pub const fn tax() -> Self {
+        Self::stmt(BPF_MISC | libc::BPF_TAX, 0)
+    }
+
+    /// A = X
+    pub const fn txa() -> Self {
+        Self::stmt(BPF_MISC | libc::BPF_TXA, 0)
+    }
+}
+
+/// Where a filter set with [`RawSock::set_filter`](crate::RawSock::set_filter) runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterMode {
+    /// Attached to the socket with `SO_ATTACH_FILTER`
+    Kernel,
+    /// Run by [`Program::run_with`] over each frame as it is read, because the kernel refused it
+    Userspace,
+}
+
+/// Points a `sock_fprog` at `filter`, which must outlive any use of the result
+pub(crate) fn fprog(filter: &[SockFilter]) -> libc::sock_fprog {
+    libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    }
+}
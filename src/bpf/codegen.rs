@@ -0,0 +1,329 @@
+//! Turns a boolean expression of tests on the frame into straight-line classic BPF.
+//!
+//! Each test becomes a node of a flow graph with a true and a false successor. Before laying the
+//! graph out, jumps into tests whose outcome is already known on that edge are threaded through
+//! to the test's successor, and loads of a value A or X already holds are dropped. These are the
+//! same optimisations libpcap relies on, so common expressions come out as `tcpdump -d` has them
+
+use super::{AluOp, SockFilter};
+
+/// How a test compares A against its constant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum Cmp {
+    Eq,
+    Gt,
+    Ge,
+    Set,
+}
+
+impl Cmp {
+    fn eval(self, a: u32, k: u32) -> bool {
+        match self {
+            Cmp::Eq => a == k,
+            Cmp::Gt => a > k,
+            Cmp::Ge => a >= k,
+            Cmp::Set => a & k != 0,
+        }
+    }
+
+    fn insn(self, k: u32, jt: u8, jf: u8) -> SockFilter {
+        match self {
+            Cmp::Eq => SockFilter::jeq(k, jt, jf),
+            Cmp::Gt => SockFilter::jgt(k, jt, jf),
+            Cmp::Ge => SockFilter::jge(k, jt, jf),
+            Cmp::Set => SockFilter::jset(k, jt, jf),
+        }
+    }
+}
+
+/// A value computed from the frame: an optional load into X, then the statements leaving it in A
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct Value {
+    x: Option<SockFilter>,
+    a: Vec<SockFilter>,
+}
+
+impl Value {
+    pub(super) fn load(insn: SockFilter) -> Self {
+        Self { x: None, a: vec![insn] }
+    }
+
+    /// A load relative to X, e.g. past a variable-length IPv4 header
+    pub(super) fn indexed(x: SockFilter, insn: SockFilter) -> Self {
+        Self { x: Some(x), a: vec![insn] }
+    }
+
+    /// The value masked with `mask`, leaving out the `and` for an all-ones mask
+    pub(super) fn masked(mut self, mask: u32) -> Self {
+        if mask != u32::MAX {
+            self.a.push(SockFilter::alu(AluOp::And, mask));
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct Test {
+    value: Value,
+    cmp: Cmp,
+    k: u32,
+}
+
+impl Test {
+    /// Whether this test is decided by another test having come out as `outcome`
+    fn decided_by(&self, known: &Test, outcome: bool) -> Option<bool> {
+        if self.value != known.value {
+            return None;
+        }
+
+        if self.cmp == known.cmp && self.k == known.k {
+            Some(outcome)
+        } else if known.cmp == Cmp::Eq && outcome {
+            Some(self.cmp.eval(known.k, self.k))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Expr {
+    True,
+    False,
+    Test(Test),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub(super) fn test(value: Value, cmp: Cmp, k: u32) -> Self {
+        Expr::Test(Test { value, cmp, k })
+    }
+
+    pub(super) fn eq(value: Value, k: u32) -> Self {
+        Self::test(value, Cmp::Eq, k)
+    }
+
+    pub(super) fn and(self, other: Expr) -> Self {
+        match (self, other) {
+            (Expr::True, e) | (e, Expr::True) => e,
+            (a, b) => Expr::And(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub(super) fn or(self, other: Expr) -> Self {
+        match (self, other) {
+            (Expr::False, e) | (e, Expr::False) => e,
+            (a, b) => Expr::Or(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub(super) fn negate(self) -> Self {
+        match self {
+            Expr::Not(e) => *e,
+            e => Expr::Not(Box::new(e)),
+        }
+    }
+
+    /// Any of the expressions, tested in order
+    pub(super) fn any(exprs: impl IntoIterator<Item = Expr>) -> Self {
+        exprs.into_iter().fold(Expr::False, Expr::or)
+    }
+
+    /// All of the expressions, tested in order
+    pub(super) fn all(exprs: impl IntoIterator<Item = Expr>) -> Self {
+        exprs.into_iter().fold(Expr::True, Expr::and)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Node(usize),
+    Accept,
+    Reject,
+}
+
+struct Node {
+    test: Test,
+    jt: Target,
+    jf: Target,
+}
+
+impl Node {
+    fn branch(&self, outcome: bool) -> Target {
+        if outcome { self.jt } else { self.jf }
+    }
+
+    fn branch_mut(&mut self, outcome: bool) -> &mut Target {
+        if outcome { &mut self.jt } else { &mut self.jf }
+    }
+}
+
+/// Generates a program accepting `snaplen` bytes of frames matching `expr`. Returns `None` if a
+/// jump ends up further than the 255 instructions an offset can hold
+pub(super) fn generate(expr: &Expr, snaplen: u32) -> Option<Vec<SockFilter>> {
+    let mut nodes = Vec::new();
+    let entry = build(&mut nodes, expr, Target::Accept, Target::Reject);
+    thread_jumps(&mut nodes, entry);
+    emit(&nodes, entry, snaplen)
+}
+
+/// Adds the nodes for `expr` to the graph and returns its entry point, going to `t` if it matches
+/// and `f` if it does not
+fn build(nodes: &mut Vec<Node>, expr: &Expr, t: Target, f: Target) -> Target {
+    match expr {
+        Expr::True => t,
+        Expr::False => f,
+        Expr::Test(test) => {
+            nodes.push(Node { test: test.clone(), jt: t, jf: f });
+            Target::Node(nodes.len() - 1)
+        }
+        Expr::Not(e) => build(nodes, e, f, t),
+        Expr::And(a, b) => {
+            let b = build(nodes, b, t, f);
+            build(nodes, a, b, f)
+        }
+        Expr::Or(a, b) => {
+            let b = build(nodes, b, t, f);
+            build(nodes, a, t, b)
+        }
+    }
+}
+
+/// The nodes reachable from `entry` in topological order. The false branch is visited first so
+/// that it is laid out after the true branch, as libpcap does
+fn order(nodes: &[Node], entry: Target) -> Vec<usize> {
+    fn visit(nodes: &[Node], target: Target, seen: &mut [bool], post: &mut Vec<usize>) {
+        if let Target::Node(i) = target && !seen[i] {
+            seen[i] = true;
+            visit(nodes, nodes[i].jf, seen, post);
+            visit(nodes, nodes[i].jt, seen, post);
+            post.push(i);
+        }
+    }
+
+    let mut seen = vec![false; nodes.len()];
+    let mut post = Vec::new();
+    visit(nodes, entry, &mut seen, &mut post);
+    post.reverse();
+    post
+}
+
+/// Redirects every jump into a test that is already decided by the tests passed on the way there
+fn thread_jumps(nodes: &mut [Node], entry: Target) {
+    loop {
+        let mut changed = false;
+        // The outcomes known on every path into each node
+        let mut known: Vec<Option<Vec<(Test, bool)>>> = vec![None; nodes.len()];
+
+        for i in order(nodes, entry) {
+            let facts = known[i].take().unwrap_or_default();
+
+            for outcome in [true, false] {
+                let mut edge = facts.clone();
+                edge.push((nodes[i].test.clone(), outcome));
+
+                let mut target = nodes[i].branch(outcome);
+                while let Target::Node(j) = target
+                    && let Some(b) = edge.iter().find_map(|(test, outcome)| nodes[j].test.decided_by(test, *outcome))
+                {
+                    edge.push((nodes[j].test.clone(), b));
+                    target = nodes[j].branch(b);
+                }
+
+                if target != nodes[i].branch(outcome) {
+                    *nodes[i].branch_mut(outcome) = target;
+                    changed = true;
+                }
+
+                if let Target::Node(j) = target {
+                    known[j] = Some(match known[j].take() {
+                        Some(prev) => prev.into_iter().filter(|fact| edge.contains(fact)).collect(),
+                        None => edge,
+                    });
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// The value every predecessor agrees on, if any
+fn common<T: PartialEq + Copy>(mut values: impl Iterator<Item = Option<T>>) -> Option<T> {
+    let first = values.next()??;
+    values.all(|value| value == Some(first)).then_some(first)
+}
+
+fn emit(nodes: &[Node], entry: Target, snaplen: u32) -> Option<Vec<SockFilter>> {
+    let order = order(nodes, entry);
+    let mut preds = vec![Vec::new(); nodes.len()];
+    for &i in &order {
+        for target in [nodes[i].jt, nodes[i].jf] {
+            if let Target::Node(j) = target {
+                preds[j].push(i);
+            }
+        }
+    }
+
+    // What A and X hold after each node, and the statements each node still needs
+    let mut a_out: Vec<Option<&Value>> = vec![None; nodes.len()];
+    let mut x_out: Vec<Option<SockFilter>> = vec![None; nodes.len()];
+    let mut stmts = vec![Vec::new(); nodes.len()];
+    for &i in &order {
+        let value = &nodes[i].test.value;
+        let a_in = common(preds[i].iter().map(|&p| a_out[p]));
+        let x_in = common(preds[i].iter().map(|&p| x_out[p]));
+
+        x_out[i] = x_in;
+        if a_in != Some(value) {
+            if let Some(x) = value.x {
+                if x_in != Some(x) {
+                    stmts[i].push(x);
+                }
+                x_out[i] = Some(x);
+            }
+            stmts[i].extend_from_slice(&value.a);
+        }
+        a_out[i] = Some(value);
+    }
+
+    // Lay the nodes out in order, with the returns they jump to at the end
+    let mut jump_at = vec![0; nodes.len()];
+    let mut len = 0;
+    for &i in &order {
+        jump_at[i] = len + stmts[i].len();
+        len = jump_at[i] + 1;
+    }
+
+    let targets = order.iter().flat_map(|&i| [nodes[i].jt, nodes[i].jf]).chain([entry]);
+    let (accepts, rejects) = targets.fold((false, false), |(a, r), t| (a || t == Target::Accept, r || t == Target::Reject));
+    let accept_at = len;
+    let reject_at = len + accepts as usize;
+
+    let address = |target| match target {
+        Target::Node(j) => jump_at[j] - stmts[j].len(),
+        Target::Accept => accept_at,
+        Target::Reject => reject_at,
+    };
+
+    let mut insns = Vec::with_capacity(reject_at + 1);
+    for &i in &order {
+        let node = &nodes[i];
+        let offset = |target| u8::try_from(address(target) - jump_at[i] - 1).ok();
+        insns.extend_from_slice(&stmts[i]);
+        insns.push(node.test.cmp.insn(node.test.k, offset(node.jt)?, offset(node.jf)?));
+    }
+
+    if accepts {
+        insns.push(SockFilter::ret(snaplen));
+    }
+    if rejects {
+        insns.push(SockFilter::ret(0));
+    }
+
+    Some(insns)
+}
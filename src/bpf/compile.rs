@@ -0,0 +1,694 @@
+//! Parsing of pcap-filter expressions into [`Expr`]s over ethernet frames
+
+use std::{error::Error, fmt, io, net::{Ipv4Addr, Ipv6Addr}};
+
+use crate::MacAddr;
+use super::{codegen::{self, Cmp, Expr, Value}, Program, SockFilter};
+
+const ETHERTYPE_IP: u32 = 0x0800;
+const ETHERTYPE_ARP: u32 = 0x0806;
+const ETHERTYPE_RARP: u32 = 0x8035;
+const ETHERTYPE_IPV6: u32 = 0x86dd;
+
+const IPPROTO_ICMP: u32 = 1;
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+const IPPROTO_ICMPV6: u32 = 58;
+const IPPROTO_SCTP: u32 = 132;
+const IPPROTO_FRAGMENT: u32 = 44;
+
+/// Why a pcap-filter expression could not be compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    offset: usize,
+    message: String,
+}
+
+impl CompileError {
+    fn new(offset: usize, message: impl Into<String>) -> Self {
+        Self { offset, message: message.into() }
+    }
+
+    /// Byte offset into the expression where the problem was found
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<CompileError> for io::Error {
+    fn from(err: CompileError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+pub(super) fn compile(expr: &str, snaplen: u32) -> Result<Program, CompileError> {
+    let mut parser = Parser { tokens: lex(expr)?, pos: 0, end: expr.len(), last: None };
+
+    let expr = if parser.tokens.is_empty() { Expr::True } else { parser.expr()? };
+    if let Some(&(offset, token)) = parser.tokens.get(parser.pos) {
+        return Err(CompileError::new(offset, format!("unexpected {token}")));
+    }
+
+    codegen::generate(&expr, snaplen)
+        .map(Program::new)
+        .ok_or_else(|| CompileError::new(0, "expression is too large for classic BPF jump offsets"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::LParen => f.write_str("`(`"),
+            Token::RParen => f.write_str("`)`"),
+            Token::Not => f.write_str("`not`"),
+            Token::And => f.write_str("`and`"),
+            Token::Or => f.write_str("`or`"),
+        }
+    }
+}
+
+fn lex(input: &str) -> Result<Vec<(usize, Token<'_>)>, CompileError> {
+    let is_special = |c: char| c.is_whitespace() || "()!&|".contains(c);
+    let mut tokens = Vec::new();
+    let mut rest = input.char_indices().peekable();
+
+    while let Some((offset, c)) = rest.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '!' => Token::Not,
+            '&' | '|' => match rest.next_if(|&(_, next)| next == c) {
+                Some(_) if c == '&' => Token::And,
+                Some(_) => Token::Or,
+                None => return Err(CompileError::new(offset, format!("expected `{c}{c}`"))),
+            },
+            _ => {
+                let mut end = offset + c.len_utf8();
+                while let Some((i, c)) = rest.next_if(|&(_, c)| !is_special(c)) {
+                    end = i + c.len_utf8();
+                }
+
+                match &input[offset..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    word => Token::Word(word),
+                }
+            }
+        };
+        tokens.push((offset, token));
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Proto {
+    Ether,
+    Ip,
+    Ip6,
+    Arp,
+    Rarp,
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+impl Proto {
+    fn from_keyword(word: &str) -> Option<Self> {
+        Some(match word {
+            "ether" => Proto::Ether,
+            "ip" => Proto::Ip,
+            "ip6" => Proto::Ip6,
+            "arp" => Proto::Arp,
+            "rarp" => Proto::Rarp,
+            "tcp" => Proto::Tcp,
+            "udp" => Proto::Udp,
+            "sctp" => Proto::Sctp,
+            _ => return None,
+        })
+    }
+
+    /// The protocol on its own, as in `ip` or `tcp`
+    fn bare(self) -> Option<Expr> {
+        Some(match self {
+            Proto::Ether => return None,
+            Proto::Ip => ether_type(ETHERTYPE_IP),
+            Proto::Ip6 => ether_type(ETHERTYPE_IPV6),
+            Proto::Arp => ether_type(ETHERTYPE_ARP),
+            Proto::Rarp => ether_type(ETHERTYPE_RARP),
+            Proto::Tcp => ip6_proto(IPPROTO_TCP).or(ip_proto(IPPROTO_TCP)),
+            Proto::Udp => ip6_proto(IPPROTO_UDP).or(ip_proto(IPPROTO_UDP)),
+            Proto::Sctp => ip6_proto(IPPROTO_SCTP).or(ip_proto(IPPROTO_SCTP)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Dir {
+    #[default]
+    Any,
+    Src,
+    Dst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Kind {
+    #[default]
+    Host,
+    Net,
+    Port,
+}
+
+/// The `[proto] [dir] [kind]` qualifiers in front of an ID, which a bare ID after `and`/`or`
+/// inherits from the previous primitive
+#[derive(Debug, Clone, Copy, Default)]
+struct Qual {
+    proto: Option<Proto>,
+    dir: Dir,
+    kind: Kind,
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    pos: usize,
+    end: usize,
+    last: Option<Qual>,
+}
+
+impl<'a> Parser<'a> {
+    /// `and` and `or` have the same precedence and associate to the left
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.unary()?;
+        loop {
+            if self.eat(Token::And) {
+                expr = expr.and(self.unary()?);
+            } else if self.eat(Token::Or) {
+                expr = expr.or(self.unary()?);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat(Token::Not) {
+            return Ok(self.unary()?.negate());
+        }
+
+        if self.eat(Token::LParen) {
+            let expr = self.expr()?;
+            if !self.eat(Token::RParen) {
+                return Err(self.expected("`)`"));
+            }
+            return Ok(expr);
+        }
+
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Expr, CompileError> {
+        let (mut offset, mut word) = self.word("a primitive")?;
+
+        match word {
+            "icmp" => return Ok(ip_proto(IPPROTO_ICMP)),
+            "icmp6" => return Ok(ip6_proto(IPPROTO_ICMPV6)),
+            "vlan" => return self.vlan(),
+            "less" => return Ok(Expr::test(Value::load(SockFilter::ld_len()), Cmp::Gt, self.number(u32::MAX)?).negate()),
+            "greater" => return Ok(Expr::test(Value::load(SockFilter::ld_len()), Cmp::Ge, self.number(u32::MAX)?)),
+            _ => {}
+        }
+
+        let mut qual = Qual::default();
+        let mut qualified = false;
+
+        if let Some(proto) = Proto::from_keyword(word) {
+            qual.proto = Some(proto);
+            qualified = true;
+
+            match self.peek_word() {
+                Some("proto") if matches!(proto, Proto::Ether | Proto::Ip | Proto::Ip6) => {
+                    self.pos += 1;
+                    return self.proto_field(proto);
+                }
+                Some("broadcast") if proto == Proto::Ether => {
+                    self.pos += 1;
+                    return Ok(ether_host(Dir::Dst, MacAddr::BROADCAST));
+                }
+                Some("multicast") if proto == Proto::Ether => {
+                    self.pos += 1;
+                    return Ok(Expr::test(Value::load(SockFilter::ldb(0)), Cmp::Set, 1));
+                }
+                Some("src" | "dst" | "host" | "net" | "port") => (offset, word) = self.word("a qualifier")?,
+                _ => return proto.bare().ok_or_else(|| self.expected("`host`, `src`, `dst`, `proto`, `broadcast` or `multicast`")),
+            }
+        }
+
+        if let Some(dir) = match word {
+            "src" => Some(Dir::Src),
+            "dst" => Some(Dir::Dst),
+            _ => None,
+        } {
+            qual.dir = dir;
+            qualified = true;
+            (offset, word) = self.word("`host`, `net`, `port` or an address")?;
+        }
+
+        if let Some(kind) = match word {
+            "host" => Some(Kind::Host),
+            "net" => Some(Kind::Net),
+            "port" => Some(Kind::Port),
+            _ => None,
+        } {
+            qual.kind = kind;
+            qualified = true;
+            (offset, word) = self.word("an address or port")?;
+        }
+
+        if !qualified {
+            qual = self.last.unwrap_or_default();
+        }
+        self.last = Some(qual);
+
+        self.id(qual, offset, word)
+    }
+
+    /// The expression for an address or port and its qualifiers
+    fn id(&mut self, qual: Qual, offset: usize, word: &str) -> Result<Expr, CompileError> {
+        let invalid = |what: &str| CompileError::new(offset, format!("`{word}` is not {what}"));
+        let mismatch = || CompileError::new(offset, format!("`{word}` does not go with the preceding qualifiers"));
+
+        match (qual.kind, qual.proto) {
+            (Kind::Port, None | Some(Proto::Ip | Proto::Ip6 | Proto::Tcp | Proto::Udp | Proto::Sctp)) => {
+                let port = parse_number(word).filter(|&port| port <= 0xffff).ok_or_else(|| invalid("a port number"))?;
+                Ok(port_expr(qual.proto, qual.dir, port))
+            }
+            (Kind::Host, Some(Proto::Ether)) => {
+                let mac = word.parse().map_err(|_| invalid("a MAC address"))?;
+                Ok(ether_host(qual.dir, mac))
+            }
+            (Kind::Host | Kind::Net, None | Some(Proto::Ip | Proto::Ip6 | Proto::Arp | Proto::Rarp)) => {
+                let (addr, len) = match word.split_once('/') {
+                    Some(_) if qual.kind == Kind::Host => return Err(invalid("a host address")),
+                    Some((addr, len)) => (addr, Some(len)),
+                    None => (word, None),
+                };
+
+                if let Ok(addr) = addr.parse::<Ipv4Addr>() {
+                    if qual.proto == Some(Proto::Ip6) {
+                        return Err(mismatch());
+                    }
+
+                    let mask = match len {
+                        Some(len) => parse_prefix(len, 32).map(|len| u32::MAX.checked_shl(32 - len).unwrap_or(0)),
+                        None if self.peek_word() == Some("mask") => {
+                            self.pos += 1;
+                            let (_, mask) = self.word("a netmask")?;
+                            mask.parse::<Ipv4Addr>().ok().map(u32::from)
+                        }
+                        None => Some(u32::MAX),
+                    }
+                    .ok_or_else(|| invalid("a network"))?;
+
+                    let addr = u32::from(addr);
+                    if addr & !mask != 0 {
+                        return Err(CompileError::new(offset, format!("`{word}` has host bits set")));
+                    }
+                    return Ok(host4(qual.proto, qual.dir, addr, mask));
+                }
+
+                if let Ok(addr) = addr.parse::<Ipv6Addr>() {
+                    if !matches!(qual.proto, None | Some(Proto::Ip6)) {
+                        return Err(mismatch());
+                    }
+
+                    let len = match len {
+                        Some(len) => parse_prefix(len, 128).ok_or_else(|| invalid("a network"))?,
+                        None => 128,
+                    };
+
+                    let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+                    let addr = u128::from(addr);
+                    if addr & !mask != 0 {
+                        return Err(CompileError::new(offset, format!("`{word}` has host bits set")));
+                    }
+                    return Ok(host6(qual.dir, addr, mask));
+                }
+
+                Err(invalid("a known primitive or address"))
+            }
+            _ => Err(mismatch()),
+        }
+    }
+
+    /// `ether proto`, `ip proto` and `ip6 proto`
+    fn proto_field(&mut self, proto: Proto) -> Result<Expr, CompileError> {
+        let (offset, word) = self.word("a protocol")?;
+        // Protocol names that are also keywords may be escaped, as in `ip proto \tcp`
+        let name = word.strip_prefix('\\').unwrap_or(word);
+
+        let number = match (proto, name) {
+            (Proto::Ether, "ip") => Some(ETHERTYPE_IP),
+            (Proto::Ether, "ip6") => Some(ETHERTYPE_IPV6),
+            (Proto::Ether, "arp") => Some(ETHERTYPE_ARP),
+            (Proto::Ether, "rarp") => Some(ETHERTYPE_RARP),
+            (Proto::Ether, _) => parse_number(name).filter(|&n| (0x0600..=0xffff).contains(&n)),
+            (_, "icmp") => Some(IPPROTO_ICMP),
+            (_, "tcp") => Some(IPPROTO_TCP),
+            (_, "udp") => Some(IPPROTO_UDP),
+            (_, "icmp6") => Some(IPPROTO_ICMPV6),
+            (_, "sctp") => Some(IPPROTO_SCTP),
+            _ => parse_number(name).filter(|&n| n <= 0xff),
+        }
+        .ok_or_else(|| CompileError::new(offset, format!("`{word}` is not a known protocol")))?;
+
+        Ok(match proto {
+            Proto::Ether => ether_type(number),
+            Proto::Ip => ip_proto(number),
+            _ => ip6_proto(number),
+        })
+    }
+
+    /// `vlan [id]`. Packet sockets get frames with the tag already stripped into metadata, so this
+    /// tests the kernel's ancillary VLAN data and leaves the offsets of later primitives alone
+    fn vlan(&mut self) -> Result<Expr, CompileError> {
+        let present = Expr::eq(Value::load(SockFilter::ld_ancillary(libc::SKF_AD_VLAN_TAG_PRESENT)), 1);
+
+        if self.peek_word().and_then(parse_number).is_none() {
+            return Ok(present);
+        }
+
+        let id = self.number(0xfff)?;
+        let tag = Value::load(SockFilter::ld_ancillary(libc::SKF_AD_VLAN_TAG)).masked(0xfff);
+        Ok(present.and(Expr::eq(tag, id)))
+    }
+
+    fn number(&mut self, max: u32) -> Result<u32, CompileError> {
+        let (offset, word) = self.word("a number")?;
+        parse_number(word)
+            .filter(|&n| n <= max)
+            .ok_or_else(|| CompileError::new(offset, format!("`{word}` is not a number up to {max}")))
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        let found = self.tokens.get(self.pos).is_some_and(|&(_, t)| t == token);
+        self.pos += found as usize;
+        found
+    }
+
+    fn peek_word(&self) -> Option<&'a str> {
+        match self.tokens.get(self.pos) {
+            Some(&(_, Token::Word(word))) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<(usize, &'a str), CompileError> {
+        match self.tokens.get(self.pos) {
+            Some(&(offset, Token::Word(word))) => {
+                self.pos += 1;
+                Ok((offset, word))
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    fn expected(&self, what: &str) -> CompileError {
+        match self.tokens.get(self.pos) {
+            Some(&(offset, token)) => CompileError::new(offset, format!("expected {what}, found {token}")),
+            None => CompileError::new(self.end, format!("expected {what}, found the end of the expression")),
+        }
+    }
+}
+
+fn parse_number(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+fn parse_prefix(len: &str, max: u32) -> Option<u32> {
+    len.parse().ok().filter(|&len| len <= max)
+}
+
+fn ether_type(ethertype: u32) -> Expr {
+    Expr::eq(Value::load(SockFilter::ldh(12)), ethertype)
+}
+
+fn ip_proto(proto: u32) -> Expr {
+    ether_type(ETHERTYPE_IP).and(Expr::eq(Value::load(SockFilter::ldb(23)), proto))
+}
+
+/// The IPv6 next header, also looking past a fragment header like libpcap does
+fn ip6_proto(proto: u32) -> Expr {
+    let next = |offset, proto| Expr::eq(Value::load(SockFilter::ldb(offset)), proto);
+    ether_type(ETHERTYPE_IPV6).and(next(20, proto).or(next(20, IPPROTO_FRAGMENT).and(next(54, proto))))
+}
+
+/// Tests the source, destination or either of two fields
+fn either(dir: Dir, src: Expr, dst: Expr) -> Expr {
+    match dir {
+        Dir::Src => src,
+        Dir::Dst => dst,
+        Dir::Any => src.or(dst),
+    }
+}
+
+fn ether_host(dir: Dir, mac: MacAddr) -> Expr {
+    let [a, b, c, d, e, f] = mac.octets();
+    let at = |offset| {
+        Expr::eq(Value::load(SockFilter::ld(offset + 2)), u32::from_be_bytes([c, d, e, f]))
+            .and(Expr::eq(Value::load(SockFilter::ldh(offset)), u16::from_be_bytes([a, b]) as u32))
+    };
+    either(dir, at(6), at(0))
+}
+
+/// IPv4 `host` and `net`, which without a protocol also match ARP and RARP sender and target addresses
+fn host4(proto: Option<Proto>, dir: Dir, addr: u32, mask: u32) -> Expr {
+    let at = |offset| Expr::eq(Value::load(SockFilter::ld(offset)).masked(mask), addr);
+    let ip = || ether_type(ETHERTYPE_IP).and(either(dir, at(26), at(30)));
+    let arp = |types: &[u32]| Expr::any(types.iter().map(|&t| ether_type(t))).and(either(dir, at(28), at(38)));
+
+    match proto {
+        Some(Proto::Ip) => ip(),
+        Some(Proto::Arp) => arp(&[ETHERTYPE_ARP]),
+        Some(Proto::Rarp) => arp(&[ETHERTYPE_RARP]),
+        _ => ip().or(arp(&[ETHERTYPE_ARP, ETHERTYPE_RARP])),
+    }
+}
+
+/// IPv6 `host` and `net`, comparing the masked address a word at a time from the last word
+fn host6(dir: Dir, addr: u128, mask: u128) -> Expr {
+    let word = |value: u128, i: u32| (value >> (96 - 32 * i)) as u32;
+    let at = |offset| {
+        Expr::all((0..4).rev().filter(|&i| word(mask, i) != 0).map(|i| {
+            Expr::eq(Value::load(SockFilter::ld(offset + 4 * i)).masked(word(mask, i)), word(addr, i))
+        }))
+    };
+    ether_type(ETHERTYPE_IPV6).and(either(dir, at(22), at(38)))
+}
+
+/// `port` over TCP, UDP and SCTP, or only the given transport. IPv4 fragments after the first
+/// carry no ports, and the IPv4 header length is variable so ports are loaded relative to it
+fn port_expr(proto: Option<Proto>, dir: Dir, port: u32) -> Expr {
+    let transports: &[u32] = match proto {
+        Some(Proto::Tcp) => &[IPPROTO_TCP],
+        Some(Proto::Udp) => &[IPPROTO_UDP],
+        Some(Proto::Sctp) => &[IPPROTO_SCTP],
+        _ => &[IPPROTO_SCTP, IPPROTO_TCP, IPPROTO_UDP],
+    };
+    let transport = |offset| Expr::any(transports.iter().map(|&p| Expr::eq(Value::load(SockFilter::ldb(offset)), p)));
+
+    let ip6_port = |offset| Expr::eq(Value::load(SockFilter::ldh(offset)), port);
+    let ip6 = || ether_type(ETHERTYPE_IPV6).and(transport(20)).and(either(dir, ip6_port(54), ip6_port(56)));
+
+    let ip_port = |offset| Expr::eq(Value::indexed(SockFilter::ldx_msh(14), SockFilter::ldh_ind(offset)), port);
+    let fragment = Expr::test(Value::load(SockFilter::ldh(20)), Cmp::Set, 0x1fff);
+    let ip = || {
+        Expr::all([ether_type(ETHERTYPE_IP), transport(23), fragment.clone().negate(), either(dir, ip_port(14), ip_port(16))])
+    };
+
+    match proto {
+        Some(Proto::Ip) => ip(),
+        Some(Proto::Ip6) => ip6(),
+        _ => ip6().or(ip()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(expr: &str) -> String {
+        Program::compile(expr).unwrap().to_string()
+    }
+
+    // Listings from `tcpdump -d` on an ethernet interface
+    #[test]
+    fn test_tcpdump_listings() {
+        assert_eq!(dump("ip"), "\
+(000) ldh      [12]
+(001) jeq      #0x800           jt 2\tjf 3
+(002) ret      #262144
+(003) ret      #0
+");
+
+        assert_eq!(dump("arp or ip"), "\
+(000) ldh      [12]
+(001) jeq      #0x806           jt 3\tjf 2
+(002) jeq      #0x800           jt 3\tjf 4
+(003) ret      #262144
+(004) ret      #0
+");
+
+        assert_eq!(dump("ether src aa:bb:cc:dd:ee:ff"), "\
+(000) ld       [8]
+(001) jeq      #0xccddeeff      jt 2\tjf 5
+(002) ldh      [6]
+(003) jeq      #0xaabb          jt 4\tjf 5
+(004) ret      #262144
+(005) ret      #0
+");
+
+        assert_eq!(dump("ether host aa:bb:cc:dd:ee:ff"), "\
+(000) ld       [8]
+(001) jeq      #0xccddeeff      jt 2\tjf 4
+(002) ldh      [6]
+(003) jeq      #0xaabb          jt 8\tjf 4
+(004) ld       [2]
+(005) jeq      #0xccddeeff      jt 6\tjf 9
+(006) ldh      [0]
+(007) jeq      #0xaabb          jt 8\tjf 9
+(008) ret      #262144
+(009) ret      #0
+");
+
+        assert_eq!(dump("icmp"), "\
+(000) ldh      [12]
+(001) jeq      #0x800           jt 2\tjf 5
+(002) ldb      [23]
+(003) jeq      #0x1             jt 4\tjf 5
+(004) ret      #262144
+(005) ret      #0
+");
+
+        assert_eq!(dump("tcp"), "\
+(000) ldh      [12]
+(001) jeq      #0x86dd          jt 2\tjf 7
+(002) ldb      [20]
+(003) jeq      #0x6             jt 10\tjf 4
+(004) jeq      #0x2c            jt 5\tjf 11
+(005) ldb      [54]
+(006) jeq      #0x6             jt 10\tjf 11
+(007) jeq      #0x800           jt 8\tjf 11
+(008) ldb      [23]
+(009) jeq      #0x6             jt 10\tjf 11
+(010) ret      #262144
+(011) ret      #0
+");
+
+        assert_eq!(dump("host 10.0.0.1"), "\
+(000) ldh      [12]
+(001) jeq      #0x800           jt 2\tjf 6
+(002) ld       [26]
+(003) jeq      #0xa000001       jt 12\tjf 4
+(004) ld       [30]
+(005) jeq      #0xa000001       jt 12\tjf 13
+(006) jeq      #0x806           jt 8\tjf 7
+(007) jeq      #0x8035          jt 8\tjf 13
+(008) ld       [28]
+(009) jeq      #0xa000001       jt 12\tjf 10
+(010) ld       [38]
+(011) jeq      #0xa000001       jt 12\tjf 13
+(012) ret      #262144
+(013) ret      #0
+");
+
+        assert_eq!(dump("tcp port 80"), "\
+(000) ldh      [12]
+(001) jeq      #0x86dd          jt 2\tjf 8
+(002) ldb      [20]
+(003) jeq      #0x6             jt 4\tjf 19
+(004) ldh      [54]
+(005) jeq      #0x50            jt 18\tjf 6
+(006) ldh      [56]
+(007) jeq      #0x50            jt 18\tjf 19
+(008) jeq      #0x800           jt 9\tjf 19
+(009) ldb      [23]
+(010) jeq      #0x6             jt 11\tjf 19
+(011) ldh      [20]
+(012) jset     #0x1fff          jt 19\tjf 13
+(013) ldxb     4*([14]&0xf)
+(014) ldh      [x + 14]
+(015) jeq      #0x50            jt 18\tjf 16
+(016) ldh      [x + 16]
+(017) jeq      #0x50            jt 18\tjf 19
+(018) ret      #262144
+(019) ret      #0
+");
+
+        assert_eq!(dump(""), "(000) ret      #262144\n");
+    }
+
+    #[test]
+    fn test_expressions() {
+        for expr in [
+            "ether proto 0x88cc",
+            "ether proto \\arp",
+            "ether broadcast or ether multicast",
+            "vlan and vlan 100",
+            "ip6 and not (udp or icmp6)",
+            "src host 10.0.0.1 and dst net 192.168.0.0/16",
+            "net 10.0.0.0 mask 255.0.0.0",
+            "ip6 host fe80::1 or dst net 2001:db8::/32",
+            "ip proto \\udp && !ip6 proto 17",
+            "udp dst port 53 or port 0x1bb",
+            "host 10.0.0.1 or 10.0.0.2 and port 22",
+            "less 64 or greater 1500",
+        ] {
+            assert!(Program::compile(expr).is_ok(), "{expr}");
+        }
+
+        for (expr, offset) in [
+            ("ip and", 6),
+            ("(tcp", 4),
+            ("tcp)", 3),
+            ("ether", 5),
+            ("host 10.0.0.300", 5),
+            ("net 10.0.0.1/8", 4),
+            ("ip6 host 10.0.0.1", 9),
+            ("tcp host 10.0.0.1", 9),
+            ("port 70000", 5),
+            ("ether proto 1000", 12),
+            ("vlan 5000", 5),
+            ("a & b", 2),
+            ("frobnicate", 0),
+        ] {
+            assert_eq!(Program::compile(expr).unwrap_err().offset(), offset, "{expr}");
+        }
+    }
+}
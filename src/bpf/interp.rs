@@ -0,0 +1,332 @@
+use std::hash::{BuildHasher, RandomState};
+
+use libc::{BPF_ABS, BPF_ALU, BPF_B, BPF_H, BPF_IMM, BPF_IND, BPF_JMP, BPF_K, BPF_LD, BPF_LDX, BPF_LEN, BPF_MEM, BPF_MISC, BPF_MSH, BPF_RET, BPF_ST, BPF_STX, BPF_W, BPF_X};
+
+use crate::PacketInfo;
+use super::Program;
+
+/// The socket buffer metadata a program can read with ancillary loads
+/// ([`SockFilter::ld_ancillary`](super::SockFilter::ld_ancillary)) when run in userspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Ancillary {
+    /// `SKF_AD_PROTOCOL`, the EtherType in host order
+    pub protocol: u16,
+    /// `SKF_AD_PKTTYPE`, one of the `PACKET_*` types
+    pub pkt_type: u8,
+    /// `SKF_AD_IFINDEX`
+    pub ifindex: u32,
+    /// `SKF_AD_HATYPE`, the ARP hardware type of the interface
+    pub hatype: u16,
+    /// `SKF_AD_MARK`
+    pub mark: u32,
+    /// `SKF_AD_QUEUE`
+    pub queue: u16,
+    /// `SKF_AD_RXHASH`
+    pub rxhash: u32,
+    /// `SKF_AD_CPU`
+    pub cpu: u32,
+    /// `SKF_AD_VLAN_TAG`, with `SKF_AD_VLAN_TAG_PRESENT` reading 1 if set
+    pub vlan_tci: Option<u16>,
+    /// `SKF_AD_VLAN_TPID`
+    pub vlan_tpid: u16,
+}
+
+impl From<&PacketInfo> for Ancillary {
+    fn from(info: &PacketInfo) -> Self {
+        Self {
+            protocol: info.protocol().value(),
+            pkt_type: info.pkt_type().into(),
+            ifindex: info.ifindex(),
+            hatype: info.hatype(),
+            vlan_tci: info.aux().and_then(|aux| aux.vlan_tci()),
+            vlan_tpid: info.aux().and_then(|aux| aux.vlan_tpid()).map_or(0, |tpid| tpid.value()),
+            ..Self::default()
+        }
+    }
+}
+
+impl Ancillary {
+    fn load(&self, offset: i32, a: u32, x: u32) -> Option<u32> {
+        Some(match offset {
+            libc::SKF_AD_PROTOCOL => self.protocol as u32,
+            libc::SKF_AD_PKTTYPE => self.pkt_type as u32,
+            libc::SKF_AD_IFINDEX => self.ifindex,
+            libc::SKF_AD_HATYPE => self.hatype as u32,
+            libc::SKF_AD_MARK => self.mark,
+            libc::SKF_AD_QUEUE => self.queue as u32,
+            libc::SKF_AD_RXHASH => self.rxhash,
+            libc::SKF_AD_CPU => self.cpu,
+            libc::SKF_AD_ALU_XOR_X => a ^ x,
+            libc::SKF_AD_VLAN_TAG => self.vlan_tci.unwrap_or(0) as u32,
+            libc::SKF_AD_VLAN_TAG_PRESENT => self.vlan_tci.is_some() as u32,
+            libc::SKF_AD_VLAN_TPID => self.vlan_tpid as u32,
+            libc::SKF_AD_RANDOM => RandomState::new().hash_one(a ^ x) as u32,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether `k` is the offset of an ancillary load the interpreter knows
+fn ancillary_offset(k: u32) -> Option<i32> {
+    let offset = (k as i32).checked_sub(libc::SKF_AD_OFF)?;
+    (0..libc::SKF_AD_MAX).contains(&offset).then_some(offset)
+}
+
+impl Program {
+    /// Runs the program over `frame` the way the kernel runs a socket filter, returning how many
+    /// bytes of it to keep, with 0 meaning drop. Ancillary loads see [`Ancillary::default`]
+    pub fn run(&self, frame: &[u8]) -> u32 {
+        self.run_with(frame, &Ancillary::default())
+    }
+
+    /// Like [`Program::run`], with the metadata ancillary loads read.
+    ///
+    /// As in the kernel, loads past the end of the frame and division by a zero X end the
+    /// program with 0. Programs the kernel would refuse to attach (see [`Program::is_valid`])
+    /// also drop everything, as do loads relative to `SKF_NET_OFF` and `SKF_LL_OFF`
+    pub fn run_with(&self, frame: &[u8], ancillary: &Ancillary) -> u32 {
+        if !self.is_valid() {
+            return 0;
+        }
+
+        self.run_unchecked(frame, ancillary)
+    }
+
+    /// [`Program::run_with`] for a program already known to be valid, which saves checking it
+    /// again on every frame
+    pub(crate) fn run_unchecked(&self, frame: &[u8], ancillary: &Ancillary) -> u32 {
+        self.interpret(frame, ancillary).unwrap_or(0)
+    }
+
+    fn interpret(&self, frame: &[u8], ancillary: &Ancillary) -> Option<u32> {
+        let load = |offset: u32, size: usize| -> Option<u32> {
+            let bytes = frame.get(offset as usize..)?.get(..size)?;
+            Some(bytes.iter().fold(0, |value, &b| value << 8 | b as u32))
+        };
+        let size = |code: u32| match code & 0x18 {
+            BPF_W => 4,
+            BPF_H => 2,
+            _ => 1,
+        };
+
+        let (mut a, mut x) = (0u32, 0u32);
+        let mut mem = [0u32; libc::BPF_MEMWORDS as usize];
+        let mut pc = 0;
+
+        loop {
+            let insn = self.instructions()[pc];
+            let (code, k) = (insn.code as u32, insn.k);
+            pc += 1;
+
+            match code & 0x07 {
+                BPF_LD => {
+                    a = match code & 0xe0 {
+                        BPF_ABS => match ancillary_offset(k) {
+                            Some(offset) => ancillary.load(offset, a, x)?,
+                            None => load(k, size(code))?,
+                        },
+                        BPF_IND => load(x.checked_add(k)?, size(code))?,
+                        BPF_LEN => frame.len() as u32,
+                        BPF_MEM => mem[k as usize],
+                        _ => k,
+                    }
+                }
+                BPF_LDX => {
+                    x = match code & 0xe0 {
+                        BPF_LEN => frame.len() as u32,
+                        BPF_MEM => mem[k as usize],
+                        BPF_MSH => (load(k, 1)? & 0xf) * 4,
+                        _ => k,
+                    }
+                }
+                BPF_ST => mem[k as usize] = a,
+                BPF_STX => mem[k as usize] = x,
+                BPF_ALU => {
+                    let operand = if code & BPF_X != 0 { x } else { k };
+                    a = match code & 0xf0 {
+                        libc::BPF_ADD => a.wrapping_add(operand),
+                        libc::BPF_SUB => a.wrapping_sub(operand),
+                        libc::BPF_MUL => a.wrapping_mul(operand),
+                        libc::BPF_DIV => a.checked_div(operand)?,
+                        libc::BPF_MOD => a.checked_rem(operand)?,
+                        libc::BPF_AND => a & operand,
+                        libc::BPF_OR => a | operand,
+                        libc::BPF_XOR => a ^ operand,
+                        libc::BPF_LSH => a.wrapping_shl(operand),
+                        libc::BPF_RSH => a.wrapping_shr(operand),
+                        _ => a.wrapping_neg(),
+                    }
+                }
+                BPF_JMP => {
+                    let operand = if code & BPF_X != 0 { x } else { k };
+                    let taken = match code & 0xf0 {
+                        libc::BPF_JA => {
+                            pc += k as usize;
+                            continue;
+                        }
+                        libc::BPF_JEQ => a == operand,
+                        libc::BPF_JGT => a > operand,
+                        libc::BPF_JGE => a >= operand,
+                        _ => a & operand != 0,
+                    };
+                    pc += if taken { insn.jt } else { insn.jf } as usize;
+                }
+                BPF_RET => return Some(if code & 0x18 == libc::BPF_A { a } else { k }),
+                _ => {
+                    if code & 0xf8 == libc::BPF_TAX {
+                        x = a;
+                    } else {
+                        a = x;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the program passes the same checks as the kernel's classic BPF checker: it is
+    /// non-empty, ends in a return, jumps stay inside it, scratch memory indices and constant
+    /// divisors and shifts are in range, and every opcode is known. Ancillary loads must also be
+    /// ones [`Ancillary`] provides, which rules out the netlink and payload offset ones
+    pub fn is_valid(&self) -> bool {
+        let insns = self.instructions();
+        let in_range = |pc: usize, skip: u32| (skip as usize) < insns.len() - pc - 1;
+
+        let Some(last) = insns.last() else {
+            return false;
+        };
+        if last.code as u32 & 0x07 != BPF_RET {
+            return false;
+        }
+
+        insns.iter().enumerate().all(|(pc, insn)| {
+            let (code, k) = (insn.code as u32, insn.k);
+            let mem = (k as usize) < libc::BPF_MEMWORDS as usize;
+
+            match code {
+                c if c == BPF_LD | BPF_W | BPF_ABS || c == BPF_LD | BPF_H | BPF_ABS || c == BPF_LD | BPF_B | BPF_ABS => {
+                    // Negative offsets are either a known ancillary load or reach outside the frame
+                    (k as i32) >= 0 || ancillary_offset(k).is_some_and(|offset| Ancillary::default().load(offset, 0, 0).is_some())
+                }
+                c if c == BPF_LD | BPF_W | BPF_IND || c == BPF_LD | BPF_H | BPF_IND || c == BPF_LD | BPF_B | BPF_IND => true,
+                c if c == BPF_LD | BPF_IMM || c == BPF_LD | BPF_W | BPF_LEN => true,
+                c if c == BPF_LDX | BPF_IMM || c == BPF_LDX | BPF_W | BPF_LEN || c == BPF_LDX | BPF_B | BPF_MSH => true,
+                c if c == BPF_LD | BPF_MEM || c == BPF_LDX | BPF_MEM || c == BPF_ST || c == BPF_STX => mem,
+                c if c == BPF_ALU | libc::BPF_NEG => true,
+                c if c == BPF_ALU | libc::BPF_DIV | BPF_K || c == BPF_ALU | libc::BPF_MOD | BPF_K => k != 0,
+                c if c == BPF_ALU | libc::BPF_LSH | BPF_K || c == BPF_ALU | libc::BPF_RSH | BPF_K => k < 32,
+                c if c & 0x07 == BPF_ALU => {
+                    matches!(c & 0xf0, libc::BPF_ADD | libc::BPF_SUB | libc::BPF_MUL | libc::BPF_DIV | libc::BPF_MOD
+                        | libc::BPF_AND | libc::BPF_OR | libc::BPF_XOR | libc::BPF_LSH | libc::BPF_RSH)
+                        && c & !0xf8 == BPF_ALU
+                }
+                c if c == BPF_JMP | libc::BPF_JA => in_range(pc, k),
+                c if c & 0x07 == BPF_JMP => {
+                    matches!(c & 0xf0, libc::BPF_JEQ | libc::BPF_JGT | libc::BPF_JGE | libc::BPF_JSET)
+                        && c & !0xf8 == BPF_JMP
+                        && in_range(pc, insn.jt as u32)
+                        && in_range(pc, insn.jf as u32)
+                }
+                c if c == BPF_RET | BPF_K || c == BPF_RET | libc::BPF_A => true,
+                c if c == BPF_MISC | libc::BPF_TAX || c == BPF_MISC | libc::BPF_TXA => true,
+                _ => false,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpf::{AluOp, SockFilter};
+
+    /// An ethernet + IPv4 + TCP frame from 10.0.0.1:40000 to 10.0.0.2:80
+    fn tcp_frame(ihl: u8, frag: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; 14 + ihl as usize * 4 + 20];
+        frame[..6].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x02]);
+        frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[14] = 0x40 | ihl;
+        frame[20..22].copy_from_slice(&frag.to_be_bytes());
+        frame[23] = 6;
+        frame[26..30].copy_from_slice(&[10, 0, 0, 1]);
+        frame[30..34].copy_from_slice(&[10, 0, 0, 2]);
+        let tcp = 14 + ihl as usize * 4;
+        frame[tcp..tcp + 2].copy_from_slice(&40000u16.to_be_bytes());
+        frame[tcp + 2..tcp + 4].copy_from_slice(&80u16.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_compiled_programs() {
+        let frame = tcp_frame(5, 0);
+        let run = |expr: &str, frame: &[u8]| Program::compile(expr).unwrap().run(frame);
+
+        for expr in ["ip", "tcp", "tcp port 80", "src port 40000", "host 10.0.0.1 and dst host 10.0.0.2", "net 10.0.0.0/8", "ether dst 02:00:00:00:00:02", "less 60"] {
+            assert_eq!(run(expr, &frame), Program::SNAPLEN, "{expr}");
+        }
+        for expr in ["ip6", "udp", "icmp", "port 81", "dst port 40000", "src host 10.0.0.2", "ether src 02:00:00:00:00:02", "vlan", "greater 100"] {
+            assert_eq!(run(expr, &frame), 0, "{expr}");
+        }
+
+        // Ports are found past IPv4 options, and not in later fragments
+        assert_eq!(run("tcp dst port 80", &tcp_frame(7, 0)), Program::SNAPLEN);
+        assert_eq!(run("tcp dst port 80", &tcp_frame(5, 0x0010)), 0);
+        // Truncated frames end the program as soon as a load falls off the end
+        assert_eq!(run("tcp port 80", &frame[..36]), 0);
+    }
+
+    #[test]
+    fn test_ancillary() {
+        let tagged = Ancillary { protocol: 0x0800, ifindex: 3, vlan_tci: Some(0x2064), vlan_tpid: 0x8100, ..Ancillary::default() };
+        let vlan = Program::compile("vlan 100").unwrap();
+        assert_eq!(vlan.run_with(&[], &tagged), Program::SNAPLEN);
+        assert_eq!(vlan.run_with(&[], &Ancillary { vlan_tci: Some(101), ..tagged }), 0);
+        assert_eq!(vlan.run(&[]), 0);
+
+        let echo = |offset| Program::new(vec![SockFilter::ld_ancillary(offset), SockFilter::ret_a()]).run_with(&[], &tagged);
+        assert_eq!(echo(libc::SKF_AD_PROTOCOL), 0x0800);
+        assert_eq!(echo(libc::SKF_AD_IFINDEX), 3);
+        assert_eq!(echo(libc::SKF_AD_VLAN_TAG), 0x2064);
+        assert_eq!(echo(libc::SKF_AD_VLAN_TPID), 0x8100);
+        assert_eq!(echo(libc::SKF_AD_PAY_OFFSET), 0);
+    }
+
+    #[test]
+    fn test_semantics() {
+        // Scratch memory, X and the ALU: ((len + 7) % 5) << 2
+        let program = Program::new(vec![
+            SockFilter::ld_len(),
+            SockFilter::alu(AluOp::Add, 7),
+            SockFilter::st(3),
+            SockFilter::ldx_imm(5),
+            SockFilter::ld_mem(3),
+            SockFilter::alu_x(AluOp::Mod),
+            SockFilter::alu(AluOp::Lsh, 2),
+            SockFilter::ret_a(),
+        ]);
+        assert_eq!(program.run(&[0; 10]), 8);
+
+        // Division by a zero X drops, as does a frame too short for an indexed load
+        let divide = Program::new(vec![SockFilter::ld_imm(10), SockFilter::ldx_len(), SockFilter::alu_x(AluOp::Div), SockFilter::ret_a()]);
+        assert_eq!(divide.run(&[0; 2]), 5);
+        assert_eq!(divide.run(&[]), 0);
+        let indexed = Program::new(vec![SockFilter::ldx_imm(2), SockFilter::ldh_ind(1), SockFilter::ret_a()]);
+        assert_eq!(indexed.run(&[0, 0, 0, 0x12, 0x34]), 0x1234);
+        assert_eq!(indexed.run(&[0, 0, 0, 0x12]), 0);
+
+        for invalid in [
+            vec![],
+            vec![SockFilter::ld_imm(1)],
+            vec![SockFilter::ja(1), SockFilter::ret(1)],
+            vec![SockFilter::jeq(0, 0, 1), SockFilter::ret(1)],
+            vec![SockFilter::ld_mem(16), SockFilter::ret(1)],
+            vec![SockFilter::alu(AluOp::Div, 0), SockFilter::ret(1)],
+            vec![SockFilter::ld_ancillary(libc::SKF_AD_NLATTR), SockFilter::ret(1)],
+            vec![SockFilter::new(0xffff, 0, 0, 0), SockFilter::ret(1)],
+        ] {
+            let program = Program::new(invalid);
+            assert!(!program.is_valid(), "{program}");
+            assert_eq!(program.run(&[0; 64]), 0);
+        }
+    }
+}
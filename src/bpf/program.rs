@@ -0,0 +1,130 @@
+use std::{fmt, ops::Deref};
+
+use libc::{BPF_ABS, BPF_ALU, BPF_B, BPF_H, BPF_IMM, BPF_IND, BPF_JMP, BPF_K, BPF_LD, BPF_LDX, BPF_LEN, BPF_MEM, BPF_MISC, BPF_MSH, BPF_RET, BPF_ST, BPF_STX, BPF_W, BPF_X};
+
+use super::{compile, CompileError, SockFilter};
+
+/// A complete classic BPF program, e.g. compiled from a pcap-filter expression with
+/// [`Program::compile`]. Derefs to its instructions, so it can be passed straight to
+/// [`RawSock::attach_filter`](crate::RawSock::attach_filter).
+///
+/// Displays as a listing in the format of `tcpdump -d`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Program {
+    insns: Vec<SockFilter>,
+}
+
+impl Program {
+    /// The snapshot length accepted frames are cut to, matching tcpdump's default
+    pub const SNAPLEN: u32 = 262144;
+
+    pub fn new(insns: Vec<SockFilter>) -> Self {
+        Self { insns }
+    }
+
+    /// Compiles a pcap-filter expression such as `tcp port 80 and not host 10.0.0.1`.
+    /// See the [module docs](super) for the supported subset
+    pub fn compile(expr: &str) -> Result<Self, CompileError> {
+        compile::compile(expr, Self::SNAPLEN)
+    }
+
+    pub fn instructions(&self) -> &[SockFilter] {
+        &self.insns
+    }
+
+    pub fn into_instructions(self) -> Vec<SockFilter> {
+        self.insns
+    }
+}
+
+impl Deref for Program {
+    type Target = [SockFilter];
+
+    fn deref(&self) -> &[SockFilter] {
+        &self.insns
+    }
+}
+
+impl From<Vec<SockFilter>> for Program {
+    fn from(insns: Vec<SockFilter>) -> Self {
+        Self::new(insns)
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (n, insn) in self.insns.iter().enumerate() {
+            writeln!(f, "{}", image(insn, n))?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders one instruction like libpcap's `bpf_image`
+fn image(insn: &SockFilter, n: usize) -> String {
+    let code = insn.code as u32;
+    let k = insn.k;
+    let (op, operand) = match code {
+        c if c == BPF_RET | BPF_K => ("ret", format!("#{}", k as i32)),
+        c if c == BPF_RET | libc::BPF_A => ("ret", String::new()),
+        c if c == BPF_LD | BPF_W | BPF_ABS => ("ld", format!("[{}]", k as i32)),
+        c if c == BPF_LD | BPF_H | BPF_ABS => ("ldh", format!("[{}]", k as i32)),
+        c if c == BPF_LD | BPF_B | BPF_ABS => ("ldb", format!("[{}]", k as i32)),
+        c if c == BPF_LD | BPF_W | BPF_LEN => ("ld", "#pktlen".to_string()),
+        c if c == BPF_LD | BPF_W | BPF_IND => ("ld", format!("[x + {}]", k as i32)),
+        c if c == BPF_LD | BPF_H | BPF_IND => ("ldh", format!("[x + {}]", k as i32)),
+        c if c == BPF_LD | BPF_B | BPF_IND => ("ldb", format!("[x + {}]", k as i32)),
+        c if c == BPF_LD | BPF_IMM => ("ld", format!("#{k:#x}")),
+        c if c == BPF_LDX | BPF_IMM => ("ldx", format!("#{k:#x}")),
+        c if c == BPF_LDX | BPF_MSH | BPF_B => ("ldxb", format!("4*([{}]&0xf)", k as i32)),
+        c if c == BPF_LD | BPF_MEM => ("ld", format!("M[{}]", k as i32)),
+        c if c == BPF_LDX | BPF_MEM => ("ldx", format!("M[{}]", k as i32)),
+        c if c == BPF_LDX | BPF_W | BPF_LEN => ("ldx", "#pktlen".to_string()),
+        c if c == BPF_ST => ("st", format!("M[{}]", k as i32)),
+        c if c == BPF_STX => ("stx", format!("M[{}]", k as i32)),
+        c if c == BPF_JMP | libc::BPF_JA => ("ja", format!("{}", n as u32 + 1 + k)),
+        c if c == BPF_MISC | libc::BPF_TAX => ("tax", String::new()),
+        c if c == BPF_MISC | libc::BPF_TXA => ("txa", String::new()),
+        c if c == BPF_ALU | libc::BPF_NEG => ("neg", String::new()),
+        c if c & 0x07 == BPF_JMP => {
+            let op = match c & 0xf0 {
+                libc::BPF_JEQ => "jeq",
+                libc::BPF_JGT => "jgt",
+                libc::BPF_JGE => "jge",
+                libc::BPF_JSET => "jset",
+                _ => return unimp(code, n),
+            };
+            let operand = if c & BPF_X != 0 { "x".to_string() } else { format!("#{k:#x}") };
+            let (jt, jf) = (n + 1 + insn.jt as usize, n + 1 + insn.jf as usize);
+            return format!("({n:03}) {op:<8} {operand:<16} jt {jt}\tjf {jf}");
+        }
+        c if c & 0x07 == BPF_ALU => {
+            let (op, hex) = match c & 0xf0 {
+                libc::BPF_ADD => ("add", false),
+                libc::BPF_SUB => ("sub", false),
+                libc::BPF_MUL => ("mul", false),
+                libc::BPF_DIV => ("div", false),
+                libc::BPF_MOD => ("mod", false),
+                libc::BPF_LSH => ("lsh", false),
+                libc::BPF_RSH => ("rsh", false),
+                libc::BPF_AND => ("and", true),
+                libc::BPF_OR => ("or", true),
+                libc::BPF_XOR => ("xor", true),
+                _ => return unimp(code, n),
+            };
+            let operand = match (c & BPF_X != 0, hex) {
+                (true, _) => "x".to_string(),
+                (false, true) => format!("#{k:#x}"),
+                (false, false) => format!("#{}", k as i32),
+            };
+            (op, operand)
+        }
+        _ => return unimp(code, n),
+    };
+
+    format!("({n:03}) {op:<8} {operand}")
+}
+
+fn unimp(code: u32, n: usize) -> String {
+    format!("({n:03}) {:<8} {code:#x}", "unimp")
+}
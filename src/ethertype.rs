@@ -0,0 +1,70 @@
+use std::fmt;
+
+/// An ethernet protocol type, stored in host byte order.
+///
+/// Conversion to network byte order happens at the syscall boundary via [`EtherType::to_network`],
+/// so values can be compared and printed the way they appear in specs and `tcpdump`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EtherType(u16);
+
+impl EtherType {
+    /// Every protocol, including frames sent from this host ([`libc::ETH_P_ALL`])
+    pub const ALL: EtherType = EtherType(libc::ETH_P_ALL as u16);
+    pub const IPV4: EtherType = EtherType(libc::ETH_P_IP as u16);
+    pub const ARP: EtherType = EtherType(libc::ETH_P_ARP as u16);
+    pub const RARP: EtherType = EtherType(libc::ETH_P_RARP as u16);
+    /// 802.1Q VLAN tag
+    pub const VLAN: EtherType = EtherType(libc::ETH_P_8021Q as u16);
+    /// 802.1ad service VLAN tag
+    pub const QINQ: EtherType = EtherType(libc::ETH_P_8021AD as u16);
+    pub const IPV6: EtherType = EtherType(libc::ETH_P_IPV6 as u16);
+    pub const MPLS_UC: EtherType = EtherType(libc::ETH_P_MPLS_UC as u16);
+    pub const MPLS_MC: EtherType = EtherType(libc::ETH_P_MPLS_MC as u16);
+    /// 802.1X port access entity
+    pub const PAE: EtherType = EtherType(libc::ETH_P_PAE as u16);
+    pub const LLDP: EtherType = EtherType(libc::ETH_P_LLDP as u16);
+    /// IEEE 1588 precision time protocol
+    pub const PTP: EtherType = EtherType(libc::ETH_P_1588 as u16);
+    /// IEEE 802 local experimental ethertype 1
+    pub const LOCAL_EXP1: EtherType = EtherType(0x88b5);
+    /// IEEE 802 local experimental ethertype 2
+    pub const LOCAL_EXP2: EtherType = EtherType(0x88b6);
+
+    /// A custom protocol type, in host byte order
+    pub const fn new(value: u16) -> Self {
+        Self(value)
+    }
+
+    /// The protocol type in host byte order
+    pub const fn value(self) -> u16 {
+        self.0
+    }
+
+    /// The protocol type in network byte order, as `sockaddr_ll` and `socket(2)` expect it
+    pub const fn to_network(self) -> u16 {
+        self.0.to_be()
+    }
+
+    /// Reads a protocol type in network byte order, e.g. from `sll_protocol`
+    pub const fn from_network(value: u16) -> Self {
+        Self(u16::from_be(value))
+    }
+}
+
+impl From<u16> for EtherType {
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+impl From<EtherType> for u16 {
+    fn from(value: EtherType) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for EtherType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#06x}", self.0)
+    }
+}
@@ -0,0 +1,121 @@
+use std::{borrow::Borrow, io, pin::Pin, task::{ready, Context, Poll}};
+
+use bytes::BytesMut;
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{stream::{self, DEFAULT_CAPACITY}, LinkAddr, PacketInfo, RawSock};
+
+/// A [`Stream`] and [`Sink`] of frames decoded and encoded by a codec, like tokio-util's
+/// `UdpFramed`. Each received frame is decoded once, as a whole, with [`Decoder::decode_eof`],
+/// and frames it returns nothing for are skipped. Each item sent is encoded into a frame of its
+/// own and sent to its [`LinkAddr`].
+///
+/// The socket can be owned, or shared as a `&RawSock` or `Arc<RawSock>`
+#[derive(Debug)]
+pub struct FramedRaw<C, T = RawSock> {
+    sock: T,
+    codec: C,
+    rd: BytesMut,
+    wr: BytesMut,
+    out_addr: Option<LinkAddr>,
+    capacity: usize,
+}
+
+impl<C, T: Borrow<RawSock>> FramedRaw<C, T> {
+    pub fn new(sock: T, codec: C) -> Self {
+        let capacity = sock.borrow().frame_capacity().unwrap_or(DEFAULT_CAPACITY);
+
+        Self {
+            sock,
+            codec,
+            rd: BytesMut::new(),
+            wr: BytesMut::new(),
+            out_addr: None,
+            capacity,
+        }
+    }
+
+    /// Sets the most bytes of each received frame passed to the decoder, by default
+    /// [`RawSock::frame_capacity`]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.sock
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.sock
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// The socket, dropping any encoded frame not yet sent
+    pub fn into_inner(self) -> T {
+        self.sock
+    }
+}
+
+// Nothing is pinned structurally: the buffers and codec are moved around freely
+impl<C, T> Unpin for FramedRaw<C, T> {}
+
+impl<C: Decoder, T: Borrow<RawSock>> Stream for FramedRaw<C, T> {
+    type Item = Result<(C::Item, PacketInfo), C::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let (mut frame, info) = ready!(stream::poll_recv_frame(this.sock.borrow(), cx, &mut this.rd, this.capacity))?;
+            if let Some(item) = this.codec.decode_eof(&mut frame)? {
+                return Poll::Ready(Some(Ok((item, info))));
+            }
+        }
+    }
+}
+
+impl<I, C: Encoder<I>, T: Borrow<RawSock>> Sink<(I, LinkAddr)> for FramedRaw<C, T> {
+    type Error = C::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), C::Error>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, (item, addr): (I, LinkAddr)) -> Result<(), C::Error> {
+        let this = self.get_mut();
+        this.codec.encode(item, &mut this.wr)?;
+        this.out_addr = Some(addr);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), C::Error>> {
+        let this = self.get_mut();
+        let Some(addr) = this.out_addr else {
+            return Poll::Ready(Ok(()));
+        };
+
+        let res = ready!(this.sock.borrow().poll_send_to(cx, &this.wr, &addr));
+        let len = this.wr.len();
+        this.wr.clear();
+        this.out_addr = None;
+
+        if res? != len {
+            return Poll::Ready(Err(io::Error::other("failed to send the entire frame").into()));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), C::Error>> {
+        self.poll_flush(cx)
+    }
+}
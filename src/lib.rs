@@ -1,131 +1,451 @@
-use std::{ffi::c_int, io, os::fd::{AsRawFd, RawFd}};
-use tokio::io::unix::AsyncFd;
-
-pub struct SockOpts<'opt> {
-    /// The ethernet protocol type to bind this socket to. [`libc::ETH_P_ALL`] for example 
-    /// would allow reading and writing all arbitrary packet types
-    protocol: c_int,
-    /// The name of the interface to bind this raw socket to
-    intf: &'opt str,
-}
+use std::{ffi::c_int, future::poll_fn, io, os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd}, sync::{Mutex, RwLock}, task::{Context, Poll}};
+
+mod addr;
+mod batch;
+pub mod bpf;
+mod ethertype;
+#[cfg(feature = "codec")]
+mod framed;
+mod membership;
+mod opts;
+mod packet;
+mod reactor;
+mod ring;
+mod split;
+mod stats;
+mod stream;
+mod sys;
+mod timestamp;
 
+pub use addr::{LinkAddr, MacAddr, ParseMacError};
+pub use batch::PacketBuf;
+pub use ethertype::EtherType;
+#[cfg(feature = "codec")]
+pub use framed::FramedRaw;
+pub use membership::{Membership, MembershipGuard};
+pub use opts::{Fanout, FanoutMode, Interface, OptsError, RxTimestamp, SockOpts, SockOptsBuilder, SocketKind};
+pub use packet::{AuxData, ChecksumStatus, PacketInfo, PacketType, TruncatedFrame};
+pub use timestamp::{TxTimestamp, TxTimestamps};
+pub use stats::{IoStats, PacketStats};
+pub use stream::{Frame, FrameSink, Frames};
+pub use split::{RawRecvHalf, RawSendHalf, ReadHalf, ReuniteError, WriteHalf};
+pub use ring::{FlushReport, RxFrame, RxRing, RxRingOpts, TxRing, TxRingOpts, TxSlot};
+
+#[derive(Debug)]
 pub struct RawSock {
-    fd: AsyncFd<RawFd>,
+    fd: reactor::Fd,
+    kind: SocketKind,
+    /// A filter the kernel refused, run over frames as they are read instead
+    filter: RwLock<Option<UserFilter>>,
+    reinsert_vlan: bool,
+    counters: stats::Counters,
 }
 
 impl RawSock {
+    /// Opens a socket, which with the `tokio` feature fails outside a tokio runtime
     pub fn new(opts: SockOpts) -> Result<Self, io::Error> {
-        unsafe {
-            if opts.intf.len() >= libc::IFNAMSIZ {
-                return Err(io::Error::other("invalid interface name - exceeds length"));
-            }
+        let sock_type = match opts.kind {
+            SocketKind::Raw => libc::SOCK_RAW,
+            SocketKind::Cooked => libc::SOCK_DGRAM,
+        };
 
-            let sock_fd = libc::socket(
+        let fd = unsafe {
+            libc::socket(
                 libc::AF_PACKET,
-                libc::SOCK_RAW | libc::SOCK_NONBLOCK,
-                opts.protocol
-            );
+                sock_type | libc::SOCK_NONBLOCK,
+                opts.protocol.to_network() as i32
+            )
+        };
 
-            if sock_fd < 0 {
-                return Err(io::Error::last_os_error())
-            }
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+        }
 
-            let mut ifreq = libc::ifreq {
-                ifr_name: [0;libc::IFNAMSIZ],
-                ifr_ifru: std::mem::zeroed(),
-            };
-
-            let intf_c = &*(opts.intf.as_bytes() as *const _ as *const [i8]);
-            ifreq.ifr_name[..intf_c.len()].copy_from_slice(intf_c);
-            
-            if libc::ioctl(
-                sock_fd,
-                libc::SIOCGIFINDEX,
-                &ifreq as *const _,
-            ) < 0 {
-                return Err(io::Error::last_os_error())
-            }
-        
-            let addr = libc::sockaddr_ll {
-                sll_family: libc::AF_PACKET as u16,
-                sll_protocol: u16::to_be(opts.protocol as u16),
-                sll_ifindex: ifreq.ifr_ifru.ifru_ifindex,
-                sll_hatype: 0,
-                sll_pkttype: 0,
-                sll_halen: 0,
-                sll_addr: [0; 8],
-            };
-            
-            if libc::bind(sock_fd, &addr as *const _ as *const libc::sockaddr, std::mem::size_of::<libc::sockaddr_ll>() as u32) < 0 {
-                return Err(io::Error::last_os_error())
+        // Closed when dropped by any of the error returns below
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let sock_fd = fd.as_raw_fd();
+
+        if let Some(size) = opts.recv_buf {
+            sys::setsockopt(sock_fd, libc::SOL_SOCKET, libc::SO_RCVBUF, &size)?;
+        }
+
+        if let Some(size) = opts.send_buf {
+            sys::setsockopt(sock_fd, libc::SOL_SOCKET, libc::SO_SNDBUF, &size)?;
+        }
+
+        if opts.ignore_outgoing {
+            sys::setsockopt(sock_fd, libc::SOL_PACKET, libc::PACKET_IGNORE_OUTGOING, &1)?;
+        }
+
+        if opts.rx_timestamp == Some(RxTimestamp::Ns) {
+            sys::setsockopt(sock_fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, &1)?;
+        }
+
+        if opts.auxdata {
+            sys::setsockopt(sock_fd, libc::SOL_PACKET, libc::PACKET_AUXDATA, &1)?;
+        }
+
+        let timestamping = opts.timestamping();
+        if timestamping != 0 {
+            sys::setsockopt(sock_fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, &timestamping)?;
+        }
+
+        let addr = libc::sockaddr_ll {
+            sll_family: libc::AF_PACKET as u16,
+            sll_protocol: opts.protocol.to_network(),
+            sll_ifindex: opts.ifindex,
+            sll_hatype: 0,
+            sll_pkttype: 0,
+            sll_halen: 0,
+            sll_addr: [0; 8],
+        };
+
+        if unsafe { libc::bind(sock_fd, &addr as *const _ as *const libc::sockaddr, std::mem::size_of::<libc::sockaddr_ll>() as u32) } < 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        if opts.promiscuous {
+            sys::packet_membership(sock_fd, libc::PACKET_ADD_MEMBERSHIP, opts.ifindex, libc::PACKET_MR_PROMISC, &[])?;
+        }
+
+        // Only a bound socket can join a fanout group
+        if let Some(fanout) = &opts.fanout {
+            sys::setsockopt(sock_fd, libc::SOL_PACKET, libc::PACKET_FANOUT, &fanout.arg())?;
+
+            match fanout.mode() {
+                FanoutMode::Cbpf(program) => sys::setsockopt(sock_fd, libc::SOL_PACKET, libc::PACKET_FANOUT_DATA, &bpf::fprog(program))?,
+                FanoutMode::Ebpf(prog_fd) => sys::setsockopt(sock_fd, libc::SOL_PACKET, libc::PACKET_FANOUT_DATA, prog_fd)?,
+                _ => {}
             }
+        }
+
+        Self::register(fd, opts.kind, opts.reinsert_vlan && opts.kind == SocketKind::Raw)
+    }
 
-            Ok(Self {
-                fd: AsyncFd::new(sock_fd).unwrap(),
-            })
+    /// Takes over an existing packet socket, e.g. one inherited from a parent process, switching
+    /// it to non-blocking mode. Fails with `InvalidInput`, closing `fd`, if it is not an
+    /// `AF_PACKET` socket of type `SOCK_RAW` or `SOCK_DGRAM`
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let sock_fd = fd.as_raw_fd();
+        if sys::getsockopt::<c_int>(sock_fd, libc::SOL_SOCKET, libc::SO_DOMAIN)? != libc::AF_PACKET {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an AF_PACKET socket"));
         }
+
+        let kind = match sys::getsockopt::<c_int>(sock_fd, libc::SOL_SOCKET, libc::SO_TYPE)? {
+            libc::SOCK_RAW => SocketKind::Raw,
+            libc::SOCK_DGRAM => SocketKind::Cooked,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a SOCK_RAW or SOCK_DGRAM packet socket")),
+        };
+
+        sys::set_nonblocking(sock_fd)?;
+        Self::register(fd, kind, false)
     }
 
+    fn register(fd: OwnedFd, kind: SocketKind, reinsert_vlan: bool) -> io::Result<Self> {
+        Ok(Self {
+            fd: reactor::Fd::new(fd)?,
+            kind,
+            filter: RwLock::new(None),
+            reinsert_vlan,
+            counters: stats::Counters::default(),
+        })
+    }
+
+    /// Reads one frame into `buf`, cutting it short without notice if it does not fit. See
+    /// [`RawSock::read_exact_frame`] and [`PacketInfo::frame_len`] for noticing, and
+    /// [`RawSock::frame_capacity`] for a buffer size that always fits
     pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
-        loop {
-            let guard = self.fd.readable().await?;
-
-            unsafe {
-                let res = libc::recv(
-                    guard.get_ref().as_raw_fd(),
-                    buf as *mut _ as *mut libc::c_void,
-                    buf.len(), 
-                    0
-                );
-
-                if res < 0 {
-                    let err = io::Error::last_os_error();
-
-                    match err.kind() {
-                        io::ErrorKind::WouldBlock => continue,
-                        _ => return Err(err)
-                    }
-                } else { 
-                    return Ok(res as usize)
-                }
+        poll_fn(|cx| self.poll_recv(cx, buf)).await
+    }
+
+    /// Like [`RawSock::read`], but also returns the link-layer metadata of the frame, including
+    /// its receive timestamp if the socket was opened with [`SockOptsBuilder::rx_timestamp`]
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, PacketInfo)> {
+        poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+    }
+
+    /// Reads one frame into `buf` like [`RawSock::read`] if one is queued, or arranges for the
+    /// task in `cx` to be woken once the socket becomes readable
+    pub fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.needs_info() {
+            return self.poll_recv_from(cx, buf).map_ok(|(len, _)| len);
+        }
+
+        self.poll_read(cx, |fd| sys::recv(fd, buf)).map(|res| self.counters.rx(res))
+    }
+
+    /// The poll counterpart of [`RawSock::recv_from`], see [`RawSock::poll_recv`]
+    pub fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, PacketInfo)>> {
+        self.poll_read(cx, |fd| self.recv_filtered(fd, buf).map(|(len, info, _)| (len, info))).map(|res| self.counters.rx_info(res))
+    }
+
+    /// Reads a frame that is already queued, like [`RawSock::read`], or fails with `WouldBlock`
+    /// straight away. Together with [`RawSock::readable`] this drains the socket without awaiting
+    /// each frame
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.needs_info() {
+            return self.try_recv_from(buf).map(|(len, _)| len);
+        }
+
+        self.counters.rx(self.fd.try_read_io(|fd| sys::recv(fd, buf)))
+    }
+
+    /// The non-blocking counterpart of [`RawSock::recv_from`], see [`RawSock::try_read`]
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, PacketInfo)> {
+        self.counters.rx_info(self.fd.try_read_io(|fd| self.recv_filtered(fd, buf).map(|(len, info, _)| (len, info))))
+    }
+
+    /// Waits until the socket may have a frame to read. The readiness lasts until a read fails
+    /// with `WouldBlock`, so this can return without a frame being queued
+    pub async fn readable(&self) -> io::Result<()> {
+        self.fd.readable().await
+    }
+
+    /// Waits until the socket may have room to send, see [`RawSock::readable`]
+    pub async fn writable(&self) -> io::Result<()> {
+        self.fd.writable().await
+    }
+
+    /// Whether reads go through `recvmsg` for the frame's metadata: the userspace filter needs it
+    /// for its ancillary loads, and the VLAN tag comes from the auxdata
+    fn needs_info(&self) -> bool {
+        self.reinsert_vlan || self.filter.read().unwrap().is_some()
+    }
+
+    /// Receives the next frame the userspace filter keeps, without waiting. Also returns whether
+    /// the frame was cut short to fit `buf`
+    fn recv_filtered(&self, fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, PacketInfo, bool)> {
+        let (len, mut info) = match &*self.filter.read().unwrap() {
+            Some(filter) => filter.recv(fd, buf)?,
+            None => {
+                let (frame_len, meta) = sys::recvmsg(fd, buf, libc::MSG_TRUNC)?;
+                (frame_len.min(buf.len()), PacketInfo::from_msg(&meta, frame_len))
             }
+        };
+
+        // A reinserted tag makes the frame 4 bytes longer, which can push its end out of `buf`
+        let len = if self.reinsert_vlan { packet::reinsert_vlan(buf, len, &mut info) } else { len };
+        Ok((len, info, info.frame_len() > len))
+    }
+
+    /// Runs the non-blocking `op` on the socket once it is readable, waking the task in `cx`
+    /// again whenever it would block
+    fn poll_read<R>(&self, cx: &mut Context<'_>, op: impl FnMut(RawFd) -> io::Result<R>) -> Poll<io::Result<R>> {
+        self.fd.poll_read_io(cx, op)
+    }
+
+    /// Runs the non-blocking `op` on the socket once it is writable, like [`RawSock::poll_read`]
+    fn poll_write<R>(&self, cx: &mut Context<'_>, op: impl FnMut(RawFd) -> io::Result<R>) -> Poll<io::Result<R>> {
+        self.fd.poll_write_io(cx, op)
+    }
+
+    /// Like [`RawSock::read`], but fails with a [`TruncatedFrame`] error rather than return part of
+    /// a frame that does not fit `buf`
+    pub async fn read_exact_frame(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let (len, info) = self.recv_from(buf).await?;
+        if info.frame_len() > buf.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, TruncatedFrame::new(info.frame_len(), buf.len())));
+        }
+
+        Ok(len)
+    }
+
+    /// The MTU of the interface the socket is bound to (`SIOCGIFMTU`)
+    pub fn mtu(&self) -> io::Result<usize> {
+        let fd = self.fd.as_raw_fd();
+        let ifindex = sys::local_addr(fd)?.sll_ifindex;
+        if ifindex == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the MTU needs a socket bound to an interface"));
+        }
+
+        sys::mtu(fd, ifindex)
+    }
+
+    /// A read buffer size that fits any frame the bound interface's MTU allows: the MTU, plus an
+    /// Ethernet header and a VLAN tag on raw sockets. Offloads that coalesce frames on receive
+    /// (GRO/LRO) can still hand the socket larger ones
+    pub fn frame_capacity(&self) -> io::Result<usize> {
+        let header = match self.kind {
+            SocketKind::Raw => 14 + 4,
+            SocketKind::Cooked => 0,
+        };
+        Ok(self.mtu()? + header)
+    }
+
+    /// Opens `count` sockets from the same options, which must include a [`Fanout`], so that the
+    /// group's traffic is split between them
+    pub fn fanout_group(opts: SockOpts, count: usize) -> io::Result<Vec<Self>> {
+        if opts.fanout.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "fanout group options have no fanout set"));
         }
+
+        (0..count).map(|_| Self::new(opts.clone())).collect()
     }
 
+    /// Attaches a classic BPF socket filter (`SO_ATTACH_FILTER`), replacing any previous one.
+    /// Frames already queued on the socket were not run through it
+    pub fn attach_filter(&self, filter: &[bpf::SockFilter]) -> io::Result<()> {
+        sys::setsockopt(self.fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &bpf::fprog(filter))?;
+        *self.filter.write().unwrap() = None;
+        Ok(())
+    }
+
+    /// Attaches `program` like [`RawSock::attach_filter`], falling back to running it in userspace
+    /// on [`RawSock::read`] and [`RawSock::recv_from`] if the kernel does not support socket
+    /// filters or rejects this one, e.g. for exceeding `optmem_max`. Frames dropped by a userspace
+    /// filter still cost a syscall each, frames it keeps are copied once more so that it can see
+    /// them whole, and rings bypass it. The fallback turns on `PACKET_AUXDATA`, which the VLAN
+    /// ancillary loads read the stripped tag from, so [`PacketInfo::aux`] is filled in from then on.
+    ///
+    /// A locked filter is never worked around, and programs the interpreter cannot run
+    /// either are refused with the kernel's error
+    pub fn set_filter(&self, program: bpf::Program) -> io::Result<bpf::FilterMode> {
+        let err = match self.attach_filter(&program) {
+            Ok(()) => return Ok(bpf::FilterMode::Kernel),
+            Err(err) => err,
+        };
+
+        let unsupported = matches!(err.raw_os_error(), Some(libc::EINVAL | libc::ENOMEM | libc::ENOPROTOOPT | libc::EOPNOTSUPP));
+        if !unsupported || !program.is_valid() {
+            return Err(err);
+        }
+
+        sys::setsockopt(self.fd.as_raw_fd(), libc::SOL_PACKET, libc::PACKET_AUXDATA, &1)?;
+
+        // Whatever the kernel had attached before is replaced all the same
+        let _ = sys::setsockopt(self.fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_DETACH_FILTER, &0);
+        let scratch = self.frame_capacity().unwrap_or(stream::DEFAULT_CAPACITY);
+        *self.filter.write().unwrap() = Some(UserFilter { program, scratch: Mutex::new(vec![0; scratch]) });
+        Ok(bpf::FilterMode::Userspace)
+    }
+
+    /// Removes the attached socket filter (`SO_DETACH_FILTER`), or the userspace one
+    pub fn detach_filter(&self) -> io::Result<()> {
+        if self.filter.write().unwrap().take().is_some() {
+            return Ok(());
+        }
+
+        sys::setsockopt(self.fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_DETACH_FILTER, &0)
+    }
+
+    /// Prevents the attached filter from being detached or replaced for the rest of the socket's
+    /// life (`SO_LOCK_FILTER`), e.g. before handing the socket to less trusted code
+    pub fn lock_filter(&self) -> io::Result<()> {
+        sys::setsockopt(self.fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_LOCK_FILTER, &1)
+    }
+
+    pub fn kind(&self) -> SocketKind {
+        self.kind
+    }
+
+    /// Sends a whole frame on the bound interface. Cooked sockets have no header to take the
+    /// destination from, so they must use [`RawSock::send_to`] instead
     pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_send(cx, buf)).await
+    }
+
+    /// Sends a frame out of the interface named by `addr`, whether or not this socket is bound to it
+    pub async fn send_to(&self, buf: &[u8], addr: &LinkAddr) -> io::Result<usize> {
+        let addr = addr.to_sockaddr();
+        poll_fn(|cx| self.poll_send_sockaddr(cx, buf, &addr)).await
+    }
+
+    /// Sends a frame like [`RawSock::write`] if the socket has room for it, or arranges for the
+    /// task in `cx` to be woken once it is writable
+    pub fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.kind == SocketKind::Cooked {
+            return Poll::Ready(Err(self.counters.error(cooked_write())));
+        }
+
+        self.poll_write(cx, |fd| sys::send(fd, buf)).map(|res| self.counters.tx(res))
+    }
+
+    /// The poll counterpart of [`RawSock::send_to`], see [`RawSock::poll_send`]
+    pub fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], addr: &LinkAddr) -> Poll<io::Result<usize>> {
+        self.poll_send_sockaddr(cx, buf, &addr.to_sockaddr())
+    }
+
+    fn poll_send_sockaddr(&self, cx: &mut Context<'_>, buf: &[u8], addr: &libc::sockaddr_ll) -> Poll<io::Result<usize>> {
+        self.poll_write(cx, |fd| sys::sendto(fd, buf, addr)).map(|res| self.counters.tx(res))
+    }
+
+    /// Sends a frame like [`RawSock::write`] if the socket has room for it right now, or fails
+    /// with `WouldBlock`
+    pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        if self.kind == SocketKind::Cooked {
+            return Err(self.counters.error(cooked_write()));
+        }
+
+        self.counters.tx(self.fd.try_write_io(|fd| sys::send(fd, buf)))
+    }
+}
+
+impl AsRawFd for RawSock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for RawSock {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Hands the socket back out of the runtime, without closing it
+impl From<RawSock> for OwnedFd {
+    fn from(sock: RawSock) -> Self {
+        sock.fd.into_inner()
+    }
+}
+
+impl IntoRawFd for RawSock {
+    fn into_raw_fd(self) -> RawFd {
+        OwnedFd::from(self).into_raw_fd()
+    }
+}
+
+/// A filter program run in userspace, see [`RawSock::set_filter`]
+#[derive(Debug)]
+struct UserFilter {
+    /// Checked by [`RawSock::set_filter`], so it is run unchecked
+    program: bpf::Program,
+    /// Frames are received whole into this, so that the program sees all of them as it would in
+    /// the kernel however short the caller's buffer is
+    scratch: Mutex<Vec<u8>>,
+}
+
+impl UserFilter {
+    /// Receives the next frame the program keeps, copying what it keeps of it into `buf`
+    fn recv(&self, fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, PacketInfo)> {
+        let mut scratch = self.scratch.lock().unwrap();
         loop {
-            let guard = self.fd.writable().await?;
-
-            unsafe {
-                let res = libc::send(
-                    guard.get_ref().as_raw_fd(),
-                    buf as *const _ as *const libc::c_void,
-                    buf.len(),
-                    0,
-                );
-
-                if res < 0 {
-                    let err = io::Error::last_os_error();
-
-                    match err.kind() {
-                        io::ErrorKind::WouldBlock => continue,
-                        _ => return Err(err)
-                    }
-                } else { 
-                    return Ok(res as usize)
-                }
+            let (frame_len, meta) = sys::recvmsg(fd, &mut scratch, libc::MSG_TRUNC)?;
+            let mut info = PacketInfo::from_msg(&meta, frame_len);
+            let frame = &scratch[..frame_len.min(scratch.len())];
+
+            let keep = self.program.run_unchecked(frame, &bpf::Ancillary::from(&info)) as usize;
+            if keep > 0 {
+                info.trim(keep);
+                let len = keep.min(frame.len()).min(buf.len());
+                buf[..len].copy_from_slice(&frame[..len]);
+                return Ok((len, info));
             }
         }
     }
 }
 
+fn cooked_write() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "cooked sockets need a destination - use send_to")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn test_creation() {
-        let my_sock = RawSock::new(SockOpts { protocol: libc::ETH_P_ALL, intf: "lo" }).unwrap();
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").build().unwrap()).unwrap();
 
         let mut my_buf = [0u8;128];
 
@@ -134,10 +454,969 @@ mod tests {
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x86, 0xdd, 0x60, 0x04, 0x90, 0x15, 0x00, 0x40, 0x3a, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x80, 0x00, 0xd0, 0x40, 0x00, 0x0a, 0x00, 0x01, 0xb9, 0xb1, 0x09, 0x68, 0x00, 0x00, 0x00, 0x00, 0x27, 0x4b, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37,
         ];
 
-        my_sock.write(&packet).await.unwrap();
+        my_sock.write(packet).await.unwrap();
         let read_size = my_sock.read(&mut my_buf).await.unwrap();
 
         assert_eq!(read_size, packet.len());
         assert_eq!(&my_buf[..read_size], packet);
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_options() {
+        let opts = SockOpts::builder()
+            .interface("lo")
+            .recv_buffer_size(1 << 20)
+            .send_buffer_size(1 << 20)
+            .promiscuous(true)
+            .ignore_outgoing(true)
+            .build()
+            .unwrap();
+
+        RawSock::new(opts).unwrap();
+    }
+
+    fn frame(ethertype: EtherType, marker: u8) -> Vec<u8> {
+        let mut frame = vec![marker; 60];
+        frame[..12].fill(0);
+        frame[12..14].copy_from_slice(&ethertype.value().to_be_bytes());
+        frame
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_ethertype_binding() {
+        let bind = |protocol| RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).ignore_outgoing(true).build().unwrap()).unwrap();
+        let exp1 = bind(EtherType::LOCAL_EXP1);
+        let exp2 = bind(EtherType::LOCAL_EXP2);
+        let all = bind(EtherType::ALL);
+        let sender = bind(EtherType::new(0));
+
+        let frames = [
+            frame(EtherType::LOCAL_EXP1, 0x21),
+            frame(EtherType::LOCAL_EXP2, 0x22),
+            frame(EtherType::LOCAL_EXP1, 0x23),
+            frame(EtherType::LOCAL_EXP2, 0x24),
+        ];
+        for frame in &frames {
+            sender.write(frame).await.unwrap();
+        }
+
+        // Each binding sees only its own protocol, so the other protocol's frames never show up in between
+        let mut buf = [0u8; 128];
+        for (sock, expected) in [(&exp1, [&frames[0], &frames[2]]), (&exp2, [&frames[1], &frames[3]])] {
+            for frame in expected {
+                let len = sock.read(&mut buf).await.unwrap();
+                assert_eq!(&buf[..len], frame);
+            }
+        }
+
+        // Other tests share `lo`, so only look for our frames among whatever else shows up
+        let mut seen = 0;
+        while seen < frames.len() {
+            let len = all.read(&mut buf).await.unwrap();
+            seen += frames.iter().filter(|frame| buf[..len] == frame[..]).count();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_recv_from() {
+        let opts = SockOpts::builder().interface("lo").build().unwrap();
+        let lo = opts.ifindex().unwrap();
+        let my_sock = RawSock::new(opts.clone()).unwrap();
+        let sender = RawSock::new(opts).unwrap();
+
+        let packet = frame(EtherType::LOCAL_EXP1, 0x31);
+        sender.write(&packet).await.unwrap();
+
+        // `lo` hands every other ETH_P_ALL socket both the outgoing copy and the looped back incoming frame
+        let mut my_buf = [0u8; 128];
+        let mut types = Vec::new();
+        while !types.contains(&PacketType::Host) {
+            let (read_size, info) = my_sock.recv_from(&mut my_buf).await.unwrap();
+            if my_buf[..read_size] != packet[..] {
+                continue;
+            }
+
+            assert_eq!(info.ifindex(), lo);
+            assert_eq!(info.hatype(), libc::ARPHRD_LOOPBACK);
+            assert_eq!(info.protocol(), EtherType::LOCAL_EXP1);
+            assert_eq!(info.mac(), Some(MacAddr([0; 6])));
+            types.push(info.pkt_type());
+        }
+
+        assert_eq!(types, [PacketType::Outgoing, PacketType::Host]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_rx_timestamps() {
+        let opts = SockOpts::builder().interface("lo").protocol(EtherType::new(0x88ba));
+        let plain = RawSock::new(opts.clone().build().unwrap()).unwrap();
+        let ns = RawSock::new(opts.clone().rx_timestamp(RxTimestamp::Ns).build().unwrap()).unwrap();
+        let software = RawSock::new(opts.rx_timestamp(RxTimestamp::Software).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let before = std::time::SystemTime::now();
+        sender.write(&frame(EtherType::new(0x88ba), 0xc1)).await.unwrap();
+
+        let mut my_buf = [0u8; 128];
+        assert_eq!(plain.recv_from(&mut my_buf).await.unwrap().1.timestamp(), None);
+        let (_, ns_info) = ns.recv_from(&mut my_buf).await.unwrap();
+        let (_, software_info) = software.recv_from(&mut my_buf).await.unwrap();
+
+        // Both are the same software timestamp, taken once as the frame entered the stack
+        let received = ns_info.timestamp().unwrap();
+        assert_eq!(software_info.timestamp(), Some(received));
+        assert!(before <= received && received <= std::time::SystemTime::now());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_tx_timestamps() {
+        let opts = SockOpts::builder().interface("lo").protocol(EtherType::new(0x88bb));
+        let sender = RawSock::new(opts.clone().tx_timestamps(true).build().unwrap()).unwrap();
+        let lo = opts.build().unwrap().ifindex().unwrap();
+
+        let before = std::time::SystemTime::now();
+        let mut sent = Vec::new();
+        for i in 0..3 {
+            sender.write(&frame(EtherType::new(0x88bb), 0xd1 + i)).await.unwrap();
+            sent.push(std::time::SystemTime::now());
+        }
+        let dest = LinkAddr::new(lo, EtherType::new(0x88bb), MacAddr([0; 6]));
+        sender.send_to(&frame(EtherType::new(0x88bb), 0xd4), &dest).await.unwrap();
+
+        let mut timestamps = sender.tx_timestamps();
+        let mut previous = before;
+        for (id, sent) in sent.into_iter().enumerate() {
+            let timestamp = timestamps.next().await.unwrap();
+            assert_eq!(timestamp.id(), id as u32);
+            assert!(previous <= timestamp.time() && timestamp.time() <= sent);
+            previous = timestamp.time();
+        }
+        // They are a stream too
+        let timestamp = futures_util::StreamExt::next(&mut timestamps).await.unwrap();
+        assert_eq!(timestamp.unwrap().id(), 3);
+
+        // With the error queue empty the wait parks, even though the looped back frames sit unread
+        let cpu_time = || {
+            let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+            unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+            std::time::Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+        };
+        let start = cpu_time();
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(500), timestamps.next()).await.is_err());
+        assert!(cpu_time() - start < std::time::Duration::from_millis(50));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_vlan_auxdata() {
+        let protocol = EtherType::new(0x88bc);
+        // The receive path strips the tag into metadata even for frames looped back by `lo`, but
+        // only ETH_P_ALL sockets see it before the tag of a VLAN with no device is dropped
+        let opts = SockOpts::builder().interface("lo").ignore_outgoing(true);
+        let vlan_sock = |opts: SockOptsBuilder| {
+            let sock = RawSock::new(opts.build().unwrap()).unwrap();
+            sock.attach_filter(&bpf::Program::compile("vlan 100").unwrap()).unwrap();
+            sock
+        };
+        let plain = vlan_sock(opts.clone());
+        let aux = vlan_sock(opts.clone().auxdata(true));
+
+        // The VLAN ancillary loads work on the stripped tag in userspace too, without asking for
+        // the auxdata
+        let fallback = RawSock::new(opts.clone().build().unwrap()).unwrap();
+        let mut insns = vec![bpf::SockFilter::ja(0); libc::BPF_MAXINSNS as usize];
+        insns.extend_from_slice(&bpf::Program::compile("vlan 100").unwrap());
+        assert_eq!(fallback.set_filter(bpf::Program::new(insns)).unwrap(), bpf::FilterMode::Userspace);
+
+        let reinsert = vlan_sock(opts.reinsert_vlan(true));
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let mut tagged = frame(protocol, 0xe1);
+        tagged.splice(12..12, [0x81, 0x00, 0x20, 0x64]);
+        let untagged = frame(protocol, 0xe1);
+        sender.write(&tagged).await.unwrap();
+        sender.write(&tagged).await.unwrap();
+
+        let mut my_buf = [0u8; 128];
+        let (read_size, info) = plain.recv_from(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], untagged);
+        assert_eq!(info.aux(), None);
+
+        let (read_size, info) = aux.recv_from(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], untagged);
+        let aux_data = info.aux().unwrap();
+        assert_eq!(aux_data.vlan_tci(), Some(0x2064));
+        assert_eq!(aux_data.vlan_tpid(), Some(EtherType::VLAN));
+        assert_eq!((aux_data.len(), aux_data.snaplen()), (untagged.len(), untagged.len()));
+
+        let read_size = fallback.read(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], untagged);
+
+        let (read_size, info) = reinsert.recv_from(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], tagged);
+        assert_eq!(info.aux().unwrap().vlan_tci(), Some(0x2064));
+
+        // A short buffer loses the end of the frame rather than the tag
+        let read_size = reinsert.read(&mut my_buf[..20]).await.unwrap();
+        assert_eq!(&my_buf[..read_size], &tagged[..20]);
+
+        // The frame the kernel hands over fits a buffer the size of the untagged frame exactly, so
+        // only the reinserted tag cuts it short. Batches read through a userspace filter agree
+        let mut userspace = vec![bpf::SockFilter::ja(0); libc::BPF_MAXINSNS as usize];
+        userspace.extend_from_slice(&bpf::Program::compile("vlan 100").unwrap());
+        for filter in [None, Some(bpf::Program::new(userspace))] {
+            if let Some(filter) = filter {
+                assert_eq!(reinsert.set_filter(filter).unwrap(), bpf::FilterMode::Userspace);
+            }
+            sender.write(&tagged).await.unwrap();
+            sender.write(&tagged).await.unwrap();
+
+            let mut bufs = [PacketBuf::new(tagged.len()), PacketBuf::new(untagged.len())];
+            assert_eq!(reinsert.read_batch(&mut bufs).await.unwrap(), 2);
+            assert_eq!((bufs[0].data(), bufs[0].is_truncated()), (&tagged[..], false));
+            assert_eq!((bufs[1].data(), bufs[1].is_truncated()), (&tagged[..untagged.len()], true));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_stats() {
+        let protocol = EtherType::new(0x88bd);
+        let opts = SockOpts::builder().interface("lo").protocol(protocol).recv_buffer_size(1).build().unwrap();
+        let my_sock = RawSock::new(opts).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        // The smallest receive buffer the kernel allows holds only a few frames
+        let packet = frame(protocol, 0xf1);
+        for _ in 0..100 {
+            sender.write(&packet).await.unwrap();
+        }
+
+        let stats = my_sock.stats().unwrap();
+        assert_eq!(stats.packets(), 100);
+        assert!(stats.drops() > 0 && stats.drops() < 100);
+        assert_eq!(stats.freeze_q_cnt(), None);
+        assert_eq!(my_sock.stats().unwrap(), PacketStats::default());
+
+        let mut my_buf = [0u8; 128];
+        for _ in 0..3 {
+            my_sock.read(&mut my_buf).await.unwrap();
+        }
+        let io_stats = my_sock.io_stats();
+        assert_eq!((io_stats.rx_packets(), io_stats.rx_bytes()), (3, 3 * packet.len() as u64));
+        assert_eq!((io_stats.tx_packets(), io_stats.total_errors()), (0, 0));
+
+        let cooked = RawSock::new(SockOpts::builder().kind(SocketKind::Cooked).build().unwrap()).unwrap();
+        assert!(cooked.write(&packet).await.is_err());
+        assert_eq!(cooked.io_stats().errors(io::ErrorKind::InvalidInput), 1);
+        assert_eq!(sender.io_stats().tx_bytes(), 100 * packet.len() as u64);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_batch() {
+        let protocol = EtherType::new(0x88be);
+        let lo = SockOpts::builder().interface("lo").build().unwrap().ifindex().unwrap();
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let mut frames: Vec<_> = (0..4).map(|i| frame(protocol, 0xa1 + i)).collect();
+        frames[2].resize(100, 0xa3);
+        let slices: Vec<&[u8]> = frames.iter().map(|frame| &frame[..]).collect();
+        assert_eq!(sender.write_batch(&slices).await.unwrap(), 4);
+        assert_eq!(sender.io_stats().tx_packets(), 4);
+
+        let mut bufs = vec![PacketBuf::new(64); 8];
+        let mut received = Vec::new();
+        while received.len() < frames.len() {
+            let n = my_sock.read_batch(&mut bufs).await.unwrap();
+            received.extend(bufs[..n].iter().cloned());
+        }
+
+        for (buf, frame) in received.iter().zip(&frames) {
+            assert_eq!(buf.data(), &frame[..frame.len().min(64)]);
+            assert_eq!(buf.is_truncated(), frame.len() > 64);
+            assert_eq!(buf.info().unwrap().frame_len(), frame.len());
+            assert_eq!(buf.info().unwrap().ifindex(), lo);
+            assert_eq!(buf.info().unwrap().protocol(), protocol);
+        }
+        assert_eq!(my_sock.io_stats().rx_packets(), 4);
+
+        // Frames a userspace filter drops never land in the buffers past the filled ones
+        let mut insns = vec![bpf::SockFilter::ja(0); libc::BPF_MAXINSNS as usize];
+        insns.extend([bpf::SockFilter::ldb(14), bpf::SockFilter::jeq(0xa2, 0, 1), bpf::SockFilter::ret(0), bpf::SockFilter::ret(u32::MAX)]);
+        assert_eq!(my_sock.set_filter(bpf::Program::new(insns)).unwrap(), bpf::FilterMode::Userspace);
+
+        let slices: Vec<&[u8]> = frames[..2].iter().rev().chain(&frames[..2]).map(|frame| &frame[..]).collect();
+        assert_eq!(sender.write_batch(&slices).await.unwrap(), 4);
+
+        let before: Vec<_> = bufs[2..].iter().map(|buf| (buf.data().to_vec(), buf.info().cloned())).collect();
+        assert_eq!(my_sock.read_batch(&mut bufs).await.unwrap(), 2);
+        assert_eq!((bufs[0].data(), bufs[1].data()), (&frames[0][..], &frames[0][..]));
+        let after: Vec<_> = bufs[2..].iter().map(|buf| (buf.data().to_vec(), buf.info().cloned())).collect();
+        assert_eq!(before, after);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_truncation() {
+        let protocol = EtherType::new(0x88bf);
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let mut packet = frame(protocol, 0xb1);
+        packet.resize(200, 0xb1);
+        for _ in 0..3 {
+            sender.write(&packet).await.unwrap();
+        }
+
+        let mut my_buf = [0u8; 128];
+        let (read_size, info) = my_sock.recv_from(&mut my_buf).await.unwrap();
+        assert_eq!((read_size, info.frame_len()), (128, 200));
+        assert_eq!(&my_buf[..], &packet[..128]);
+
+        let err = my_sock.read_exact_frame(&mut my_buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let truncated = err.get_ref().unwrap().downcast_ref::<TruncatedFrame>().unwrap();
+        assert_eq!((truncated.len(), truncated.capacity()), (200, 128));
+
+        // lo's MTU is 64KiB
+        assert_eq!(my_sock.mtu().unwrap(), 65536);
+        let mut big_buf = vec![0; my_sock.frame_capacity().unwrap()];
+        let read_size = my_sock.read_exact_frame(&mut big_buf).await.unwrap();
+        assert_eq!(&big_buf[..read_size], packet);
+
+        assert!(RawSock::new(SockOpts::builder().build().unwrap()).unwrap().mtu().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_spurious_readiness() {
+        let protocol = EtherType::new(0x88c0);
+        let my_sock = std::sync::Arc::new(RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap());
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        // Let the socket see a frame become readable, then take it behind the reactor's back so
+        // the readiness it holds on to is stale
+        let packet = frame(protocol, 0xd1);
+        sender.write(&packet).await.unwrap();
+        my_sock.readable().await.unwrap();
+        let mut my_buf = [0u8; 128];
+        assert_eq!(unsafe { libc::recv(my_sock.fd.as_raw_fd(), my_buf.as_mut_ptr() as *mut libc::c_void, my_buf.len(), 0) }, 60);
+
+        // A read spinning on the stale readiness would never return from its first poll
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sock = my_sock.clone();
+        std::thread::spawn(move || {
+            let mut my_buf = [0u8; 128];
+            let mut cx = Context::from_waker(std::task::Waker::noop());
+            let poll = std::pin::pin!(sock.read(&mut my_buf)).as_mut().poll(&mut cx);
+            tx.send(poll.is_pending()).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(std::time::Duration::from_secs(5)), Ok(true));
+
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        assert!(my_sock.poll_recv(&mut cx, &mut my_buf).is_pending());
+
+        sender.write(&packet).await.unwrap();
+        let read_size = poll_fn(|cx| my_sock.poll_recv(cx, &mut my_buf)).await.unwrap();
+        assert_eq!(&my_buf[..read_size], packet);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_try_io() {
+        let protocol = EtherType::new(0x88c1);
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let mut my_buf = [0u8; 128];
+        assert_eq!(my_sock.try_read(&mut my_buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        let frames: Vec<_> = (0..3).map(|i| frame(protocol, 0xe1 + i)).collect();
+        sender.writable().await.unwrap();
+        for frame in &frames {
+            assert_eq!(sender.try_write(frame).unwrap(), frame.len());
+        }
+
+        // Drain whatever is queued on each wakeup, as an event loop would
+        let mut received = Vec::new();
+        while received.len() < frames.len() {
+            my_sock.readable().await.unwrap();
+            loop {
+                match my_sock.try_read(&mut my_buf) {
+                    Ok(read_size) => received.push(my_buf[..read_size].to_vec()),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => panic!("{err}"),
+                }
+            }
+        }
+        assert_eq!(received, frames);
+
+        sender.try_write(&frames[0]).unwrap();
+        my_sock.readable().await.unwrap();
+        let (read_size, info) = my_sock.try_recv_from(&mut my_buf).unwrap();
+        assert_eq!(&my_buf[..read_size], frames[0]);
+        assert_eq!(info.protocol(), protocol);
+        assert_eq!(my_sock.try_recv_from(&mut my_buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        // Would-blocks are not counted as errors
+        assert_eq!(my_sock.io_stats().total_errors(), 0);
+        assert_eq!(my_sock.io_stats().rx_packets(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_split() {
+        fn assert_shareable<T: Send + Sync + 'static>() {}
+        assert_shareable::<RawRecvHalf>();
+        assert_shareable::<RawSendHalf>();
+
+        let protocol = EtherType::new(0x88c2);
+        let opts = SockOpts::builder().interface("lo").protocol(protocol).build().unwrap();
+        let my_sock = RawSock::new(opts.clone()).unwrap();
+
+        let packet = frame(protocol, 0xc1);
+        let (read_half, write_half) = my_sock.split();
+        write_half.write(&packet).await.unwrap();
+        let mut my_buf = [0u8; 128];
+        let read_size = read_half.read(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], packet);
+
+        // The receiving task is already waiting when the sending one starts
+        let (recv_half, send_half) = my_sock.into_split();
+        let frames: Vec<_> = (0..4).map(|i| frame(protocol, 0xc2 + i)).collect();
+        let receiver = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut my_buf = [0u8; 128];
+            while received.len() < 4 {
+                let read_size = recv_half.read(&mut my_buf).await.unwrap();
+                received.push(my_buf[..read_size].to_vec());
+            }
+            (recv_half, received)
+        });
+        let sender = tokio::spawn(async move {
+            for frame in &frames {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                send_half.write(frame).await.unwrap();
+            }
+            (send_half, frames)
+        });
+
+        let (recv_half, received) = receiver.await.unwrap();
+        let (send_half, frames) = sender.await.unwrap();
+        assert_eq!(received, frames);
+
+        let (other_recv, other_send) = RawSock::new(opts).unwrap().into_split();
+        let ReuniteError(recv_half, other_send) = recv_half.reunite(other_send).unwrap_err();
+        drop((other_recv, other_send));
+        let my_sock = send_half.reunite(recv_half).unwrap();
+        assert_eq!(my_sock.io_stats().tx_packets(), 5);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_stream_sink() {
+        use futures_util::{SinkExt, StreamExt};
+
+        let protocol = EtherType::new(0x88c3);
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let frames: Vec<_> = (0..4).map(|i| bytes::Bytes::from(frame(protocol, 0xc1 + i))).collect();
+        let mut sink = sender.sink().buffer(3);
+        for frame in &frames[..2] {
+            sink.feed(frame.clone()).await.unwrap();
+        }
+        // Nothing goes out until the buffer fills up or the sink is flushed
+        assert_eq!(sender.io_stats().tx_packets(), 0);
+        sink.send(frames[2].clone()).await.unwrap();
+        assert_eq!(sender.io_stats().tx_packets(), 3);
+        sink.send(frames[3].clone()).await.unwrap();
+
+        let received: Vec<_> = my_sock.frames().take(4).map(Result::unwrap).collect().await;
+        assert_eq!(received.iter().map(|frame| frame.data()).collect::<Vec<_>>(), frames.iter().collect::<Vec<_>>());
+        assert!(received.iter().all(|frame| frame.info().protocol() == protocol));
+
+        // Frames are cut back to back out of one allocation
+        for pair in received.windows(2) {
+            assert_eq!(pair[0].as_ptr().wrapping_add(pair[0].len()), pair[1].as_ptr());
+        }
+
+        // Frames longer than the stream's capacity are cut short, also with the socket owned
+        sender.write(&frame(protocol, 0xcf)).await.unwrap();
+        let mut owned = Frames::new(my_sock).capacity(20);
+        let frame = owned.next().await.unwrap().unwrap();
+        assert_eq!((frame.len(), frame.info().frame_len()), (20, 60));
+
+        let cooked = RawSock::new(SockOpts::builder().kind(SocketKind::Cooked).build().unwrap()).unwrap();
+        assert!(FrameSink::new(std::sync::Arc::new(cooked)).send(frames[0].clone()).await.is_err());
+    }
+
+    /// Sequence numbers carried after the Ethernet header, in frames marked with a 0x5e
+    #[cfg(feature = "codec")]
+    struct SeqCodec;
+
+    #[cfg(feature = "codec")]
+    impl tokio_util::codec::Decoder for SeqCodec {
+        type Item = u16;
+        type Error = io::Error;
+
+        fn decode(&mut self, src: &mut bytes::BytesMut) -> io::Result<Option<u16>> {
+            let frame = src.split();
+            match frame.get(14..17) {
+                Some([hi, lo, 0x5e]) => Ok(Some(u16::from_be_bytes([*hi, *lo]))),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[cfg(feature = "codec")]
+    impl tokio_util::codec::Encoder<u16> for SeqCodec {
+        type Error = io::Error;
+
+        fn encode(&mut self, seq: u16, dst: &mut bytes::BytesMut) -> io::Result<()> {
+            let mut frame = frame(EtherType::new(0x88c4), 0x5e);
+            frame[14..16].copy_from_slice(&seq.to_be_bytes());
+            dst.extend_from_slice(&frame);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "codec")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_framed() {
+        use futures_util::{SinkExt, StreamExt};
+
+        let protocol = EtherType::new(0x88c4);
+        let opts = SockOpts::builder().interface("lo").protocol(protocol).build().unwrap();
+        let lo = LinkAddr::new(opts.ifindex().unwrap(), protocol, MacAddr([0; 6]));
+        let my_sock = std::sync::Arc::new(RawSock::new(opts).unwrap());
+        let mut framed = FramedRaw::new(my_sock.clone(), SeqCodec);
+        let mut sender = FramedRaw::new(RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap(), SeqCodec);
+
+        sender.send((1, lo)).await.unwrap();
+        // Frames the decoder has nothing for are skipped
+        sender.get_ref().write(&frame(protocol, 0x00)).await.unwrap();
+        sender.send((2, lo)).await.unwrap();
+        assert_eq!(sender.get_ref().io_stats().tx_packets(), 3);
+
+        for seq in [1, 2] {
+            let (item, info) = framed.next().await.unwrap().unwrap();
+            assert_eq!(item, seq);
+            assert_eq!(info.protocol(), protocol);
+        }
+        assert_eq!(my_sock.io_stats().rx_packets(), 3);
+
+        // The frames handed to the decoder are cut back to back out of one allocation, like those
+        // of RawSock::frames
+        let raw = FramedRaw::new(&*my_sock, tokio_util::codec::BytesCodec::new());
+        for marker in [0x01, 0x02] {
+            sender.get_ref().write(&frame(protocol, marker)).await.unwrap();
+        }
+        let kept: Vec<_> = raw.take(2).map(Result::unwrap).collect().await;
+        assert_eq!(kept[0].0.as_ptr().wrapping_add(kept[0].0.len()), kept[1].0.as_ptr());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_send_to() {
+        let opts = SockOpts::builder().interface("lo").protocol(EtherType::LOCAL_EXP2).build().unwrap();
+        let lo = opts.ifindex().unwrap();
+        let my_sock = RawSock::new(opts).unwrap();
+        let unbound = RawSock::new(SockOpts::builder().protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let packet = frame(EtherType::LOCAL_EXP2, 0x41);
+        let dest = LinkAddr::new(lo, EtherType::LOCAL_EXP2, MacAddr([0; 6]));
+        unbound.send_to(&packet, &dest).await.unwrap();
+
+        let mut my_buf = [0u8; 128];
+        loop {
+            let (read_size, info) = my_sock.recv_from(&mut my_buf).await.unwrap();
+            if my_buf[..read_size] == packet[..] {
+                assert_eq!(info.ifindex(), lo);
+                break;
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_cooked() {
+        let opts = SockOpts::builder().interface("lo").kind(SocketKind::Cooked);
+        let cooked = RawSock::new(opts.clone().protocol(EtherType::LOCAL_EXP1).build().unwrap()).unwrap();
+        let lo = SockOpts::builder().interface("lo").build().unwrap().ifindex().unwrap();
+
+        let raw = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::LOCAL_EXP2).build().unwrap()).unwrap();
+        assert_eq!(cooked.write(b"payload").await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // The kernel builds the ethernet header for cooked sends...
+        let payload = [0x51u8; 46];
+        let dest = LinkAddr::new(lo, EtherType::LOCAL_EXP2, MacAddr([0; 6]));
+        cooked.send_to(&payload, &dest).await.unwrap();
+
+        let mut my_buf = [0u8; 128];
+        let packet = frame(EtherType::LOCAL_EXP2, 0x51);
+        loop {
+            let read_size = raw.read(&mut my_buf).await.unwrap();
+            if my_buf[..read_size] == packet[..] {
+                break;
+            }
+        }
+
+        // ...and strips it from cooked receives
+        raw.send_to(&frame(EtherType::LOCAL_EXP1, 0x52), &LinkAddr::new(lo, EtherType::LOCAL_EXP1, MacAddr([0; 6]))).await.unwrap();
+        loop {
+            let (read_size, info) = cooked.recv_from(&mut my_buf).await.unwrap();
+            if my_buf[..read_size] == [0x52; 46] {
+                assert_eq!(info.protocol(), EtherType::LOCAL_EXP1);
+                assert_eq!(info.mac(), Some(MacAddr([0; 6])));
+                break;
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_rx_ring() {
+        let opts = SockOpts::builder().interface("lo").protocol(EtherType::LOCAL_EXP1).build().unwrap();
+        let lo = opts.ifindex().unwrap();
+        let ring_opts = RxRingOpts::default()
+            .block_size(1 << 16)
+            .block_count(4)
+            .retire_timeout(std::time::Duration::from_millis(10));
+        let mut ring = RxRing::new(RawSock::new(opts).unwrap(), ring_opts).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let frames: Vec<_> = (0..3).map(|i| frame(EtherType::LOCAL_EXP1, 0x61 + i)).collect();
+        for frame in &frames {
+            sender.write(frame).await.unwrap();
+        }
+
+        for expected in &frames {
+            let frame = ring.next_frame().await.unwrap();
+            assert_eq!(frame.data(), &expected[..]);
+            assert_eq!(frame.len(), expected.len());
+            assert_eq!(frame.info().ifindex(), lo);
+            assert_eq!(frame.info().protocol(), EtherType::LOCAL_EXP1);
+            assert!(frame.timestamp() > std::time::SystemTime::UNIX_EPOCH);
+        }
+
+        // Frames sent after the first block was handed back land in the next one
+        sender.write(&frames[0]).await.unwrap();
+        assert_eq!(ring.next_frame().await.unwrap().data(), &frames[0][..]);
+        assert!(ring.stats().unwrap().freeze_q_cnt().is_some());
+
+        assert!(RxRing::new(RawSock::new(SockOpts::builder().build().unwrap()).unwrap(), RxRingOpts::default().block_size(1000)).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_tx_ring() {
+        let opts = SockOpts::builder().interface("lo").protocol(EtherType::LOCAL_EXP2).build().unwrap();
+        let my_sock = RawSock::new(opts).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        // Frames bigger than lo's 64KiB MTU are rejected by the kernel rather than by `commit`
+        let mut ring = TxRing::new(sender, TxRingOpts::default().frame_size(1 << 17).frame_count(8)).unwrap();
+        let frames: Vec<_> = (0..4).map(|i| frame(EtherType::LOCAL_EXP2, 0x71 + i)).collect();
+        let oversized = frame(EtherType::LOCAL_EXP2, 0x7f).repeat(1200);
+
+        assert!(ring.push(&frames[0]));
+        assert!(ring.push(&oversized));
+        for frame in &frames[1..] {
+            let mut slot = ring.slot().unwrap();
+            slot.buf()[..frame.len()].copy_from_slice(frame);
+            slot.commit(frame.len());
+        }
+        assert_eq!(ring.queued(), 5);
+
+        assert_eq!(ring.flush().await.unwrap(), FlushReport { sent: 4, failed: 1 });
+        assert_eq!(ring.queued(), 0);
+
+        let mut my_buf = [0u8; 128];
+        for expected in &frames {
+            let read_size = my_sock.read(&mut my_buf).await.unwrap();
+            assert_eq!(&my_buf[..read_size], expected);
+        }
+
+        // The ring keeps going once the rejected frame is out of the way
+        for _ in 0..8 {
+            assert!(ring.push(&frames[0]));
+        }
+        assert!(!ring.push(&frames[0]));
+        assert_eq!(ring.flush().await.unwrap(), FlushReport { sent: 8, failed: 0 });
+    }
+
+    /// Runs `ip` or `tc`, returning whether it succeeded
+    fn run(cmd: &str, args: &str) -> bool {
+        std::process::Command::new(cmd).args(args.split(' ')).stderr(std::process::Stdio::null()).status().is_ok_and(|status| status.success())
+    }
+
+    /// A veth pair, deleted again when dropped
+    struct Veth(&'static str);
+
+    impl Drop for Veth {
+        fn drop(&mut self) {
+            run("ip", &format!("link del {}", self.0));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_tx_ring_backpressure() {
+        run("ip", "link del rawtx0");
+        if !run("ip", "link add rawtx0 type veth peer name rawtx1") {
+            eprintln!("skipping: no veth support");
+            return;
+        }
+        let _veth = Veth("rawtx0");
+
+        // A slow qdisc holds on to sent frames, keeping a tiny send buffer full for a while
+        assert!(run("ip", "link set rawtx1 up") && run("ip", "link set rawtx0 up"));
+        assert!(run("tc", "qdisc add dev rawtx0 root tbf rate 50kbit burst 1600 limit 1000000"));
+
+        let sender = RawSock::new(SockOpts::builder().interface("rawtx0").protocol(EtherType::new(0)).send_buffer_size(1).build().unwrap()).unwrap();
+        let mut packet = frame(EtherType::LOCAL_EXP2, 0x7e);
+        packet.resize(1000, 0x7e);
+        while unsafe { libc::send(sender.as_raw_fd(), packet.as_ptr() as *const libc::c_void, packet.len(), 0) } > 0 {}
+        assert_eq!(io::Error::last_os_error().kind(), io::ErrorKind::WouldBlock);
+
+        // The ring's `send` fails with EAGAIN, leaving every frame queued, until the qdisc drains
+        let mut ring = TxRing::new(sender, TxRingOpts::default().frame_count(4)).unwrap();
+        while ring.push(&packet) {}
+
+        let mut flush = std::pin::pin!(ring.flush());
+        let start = std::time::Instant::now();
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        assert!(flush.as_mut().poll(&mut cx).is_pending());
+        assert!(start.elapsed() < std::time::Duration::from_millis(20));
+
+        assert_eq!(flush.await.unwrap(), FlushReport { sent: 4, failed: 0 });
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_fanout() {
+        let opts = SockOpts::builder()
+            .interface("lo")
+            .protocol(EtherType::LOCAL_EXP1)
+            .fanout(Fanout::new(0x4242, FanoutMode::Lb));
+        let group = RawSock::fanout_group(opts.clone().build().unwrap(), 2).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+        assert!(RawSock::fanout_group(SockOpts::builder().build().unwrap(), 2).is_err());
+
+        let frames: Vec<_> = (0..4).map(|i| frame(EtherType::LOCAL_EXP1, 0x81 + i)).collect();
+        for frame in &frames {
+            sender.write(frame).await.unwrap();
+        }
+
+        // Round-robin hands each socket every other frame, and no frame to both
+        let mut received = Vec::new();
+        let mut my_buf = [0u8; 128];
+        for sock in &group {
+            for _ in 0..2 {
+                let read_size = sock.read(&mut my_buf).await.unwrap();
+                received.push(my_buf[..read_size].to_vec());
+            }
+        }
+
+        received.sort();
+        assert_eq!(received, frames);
+
+        // Joining with a different mode is refused by the kernel
+        assert!(RawSock::new(opts.clone().fanout(Fanout::new(0x4242, FanoutMode::Hash)).build().unwrap()).is_err());
+
+        // A classic BPF program returning 1 sends everything to the second socket
+        let ret_one = bpf::SockFilter::ret(1);
+        let group = RawSock::fanout_group(opts.fanout(Fanout::new(0x4243, FanoutMode::Cbpf(vec![ret_one]))).build().unwrap(), 2).unwrap();
+        for frame in &frames {
+            sender.write(frame).await.unwrap();
+        }
+        for expected in &frames {
+            let read_size = group[1].read(&mut my_buf).await.unwrap();
+            assert_eq!(&my_buf[..read_size], expected);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_filter() {
+        use bpf::SockFilter;
+
+        let protocol = EtherType::new(0x88b7);
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        // Keep the first 20 bytes of frames whose payload starts with 0x92
+        let filter = [
+            SockFilter::ldb(14),
+            SockFilter::jeq(0x92, 0, 1),
+            SockFilter::ret(20),
+            SockFilter::ret(0),
+        ];
+        my_sock.attach_filter(&filter).unwrap();
+
+        for marker in [0x91, 0x92, 0x93] {
+            sender.write(&frame(protocol, marker)).await.unwrap();
+        }
+
+        let mut my_buf = [0u8; 128];
+        let read_size = my_sock.read(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], &frame(protocol, 0x92)[..20]);
+
+        my_sock.detach_filter().unwrap();
+        sender.write(&frame(protocol, 0x94)).await.unwrap();
+        let read_size = my_sock.read(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], frame(protocol, 0x94));
+
+        my_sock.attach_filter(&filter).unwrap();
+        my_sock.lock_filter().unwrap();
+        assert!(my_sock.detach_filter().is_err());
+        assert!(my_sock.attach_filter(&[SockFilter::ret(0)]).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_membership() {
+        let lo_flags = || {
+            let flags = std::fs::read_to_string("/sys/class/net/lo/flags").unwrap();
+            i32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).unwrap()
+        };
+        let group = MacAddr([0x01, 0x00, 0x5e, 0x7f, 0x00, 0x42]);
+        let joined = || std::fs::read_to_string("/proc/net/dev_mcast").unwrap().contains("01005e7f0042");
+
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").build().unwrap()).unwrap();
+        let unbound = RawSock::new(SockOpts::builder().build().unwrap()).unwrap();
+        assert_eq!(unbound.add_membership(Membership::Promisc).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+        let promisc = my_sock.add_membership(Membership::Promisc).unwrap();
+        assert_eq!(promisc.ifindex(), SockOpts::builder().interface("lo").build().unwrap().ifindex().unwrap());
+
+        {
+            let _all_multi = my_sock.add_membership(Membership::AllMulti).unwrap();
+            let _group = my_sock.add_membership(Membership::Multicast(group)).unwrap();
+            assert_ne!(lo_flags() & libc::IFF_ALLMULTI, 0);
+            assert!(joined());
+        }
+        assert_eq!(lo_flags() & libc::IFF_ALLMULTI, 0);
+        assert!(!joined());
+        drop(promisc);
+
+        // Closing the socket drops whatever memberships outlived their guards
+        std::mem::forget(my_sock.add_membership(Membership::Multicast(group)).unwrap());
+        assert!(joined());
+        drop(my_sock);
+        assert!(!joined());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_fd_ownership() {
+        let cooked = RawSock::new(SockOpts::builder().interface("lo").kind(SocketKind::Cooked).build().unwrap()).unwrap();
+        let raw_fd = cooked.as_raw_fd();
+        assert_eq!(cooked.as_fd().as_raw_fd(), raw_fd);
+
+        let fd = OwnedFd::from(cooked);
+        assert_eq!(fd.as_raw_fd(), raw_fd);
+        let cooked = RawSock::from_fd(fd).unwrap();
+        assert_eq!(cooked.kind(), SocketKind::Cooked);
+        assert_eq!(cooked.mtu().unwrap(), 65536);
+
+        // Inherited sockets are usually blocking, and must not block the runtime
+        let blocking = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+        let raw = RawSock::from_fd(unsafe { OwnedFd::from_raw_fd(blocking) }).unwrap();
+        assert_eq!(raw.kind(), SocketKind::Raw);
+        assert_ne!(unsafe { libc::fcntl(raw.as_raw_fd(), libc::F_GETFL) } & libc::O_NONBLOCK, 0);
+        assert_eq!(raw.try_read(&mut [0; 64]).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        let _ = raw.into_raw_fd();
+        assert_eq!(unsafe { libc::close(blocking) }, 0);
+
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = RawSock::from_fd(udp.into()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_no_runtime() {
+        let err = RawSock::new(SockOpts::builder().interface("lo").build().unwrap()).unwrap_err();
+        assert_eq!((err.kind(), err.to_string()), (io::ErrorKind::Other, "no tokio reactor running".to_string()));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_compiled_filter() {
+        let protocol = EtherType::new(0x88b8);
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        let program = bpf::Program::compile("ether src 02:00:00:00:00:02 or ether dst 02:00:00:00:00:03").unwrap();
+        my_sock.attach_filter(&program).unwrap();
+
+        let mut frames = Vec::new();
+        for (i, (dst, src)) in [(1, 1), (1, 2), (4, 4), (3, 1)].into_iter().enumerate() {
+            let mut frame = frame(protocol, 0xa1 + i as u8);
+            frame[5] = dst;
+            frame[11] = src;
+            frame[0] = 2;
+            frame[6] = 2;
+            sender.write(&frame).await.unwrap();
+            frames.push(frame);
+        }
+
+        let mut my_buf = [0u8; 128];
+        for expected in [&frames[1], &frames[3]] {
+            let read_size = my_sock.read(&mut my_buf).await.unwrap();
+            assert_eq!(&my_buf[..read_size], expected);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_userspace_filter() {
+        let protocol = EtherType::new(0x88b9);
+        let my_sock = RawSock::new(SockOpts::builder().interface("lo").protocol(protocol).build().unwrap()).unwrap();
+        let sender = RawSock::new(SockOpts::builder().interface("lo").protocol(EtherType::new(0)).build().unwrap()).unwrap();
+
+        // Keep 20 bytes of frames whose payload starts with 0xb2. The kernel refuses programs
+        // longer than BPF_MAXINSNS, so this one has to run in userspace
+        let mut insns = vec![bpf::SockFilter::ja(0); libc::BPF_MAXINSNS as usize];
+        insns.extend([
+            bpf::SockFilter::ld_ancillary(libc::SKF_AD_PROTOCOL),
+            bpf::SockFilter::jeq(0x88b9, 0, 3),
+            bpf::SockFilter::ldb(14),
+            bpf::SockFilter::jeq(0xb2, 0, 1),
+            bpf::SockFilter::ret(20),
+            bpf::SockFilter::ret(0),
+        ]);
+        assert_eq!(my_sock.set_filter(bpf::Program::new(insns)).unwrap(), bpf::FilterMode::Userspace);
+        assert_eq!(my_sock.set_filter(bpf::Program::new(vec![])).unwrap_err().raw_os_error(), Some(libc::EINVAL));
+
+        for marker in [0xb1, 0xb2, 0xb3, 0xb2] {
+            sender.write(&frame(protocol, marker)).await.unwrap();
+        }
+
+        let mut my_buf = [0u8; 128];
+        let read_size = my_sock.read(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], &frame(protocol, 0xb2)[..20]);
+        // The frame is trimmed to what the filter kept, as the kernel would, so nothing was cut short
+        let (read_size, info) = my_sock.recv_from(&mut my_buf).await.unwrap();
+        assert_eq!((read_size, info.frame_len(), info.protocol()), (20, 20, protocol));
+
+        // Detaching takes the userspace filter away, and a short program goes to the kernel
+        my_sock.detach_filter().unwrap();
+        sender.write(&frame(protocol, 0xb4)).await.unwrap();
+        let read_size = my_sock.read(&mut my_buf).await.unwrap();
+        assert_eq!(&my_buf[..read_size], frame(protocol, 0xb4));
+        assert_eq!(my_sock.set_filter(bpf::Program::compile("ip").unwrap()).unwrap(), bpf::FilterMode::Kernel);
+
+        // The program sees whole frames however short the read buffer, as it would in the kernel
+        let mut insns = vec![bpf::SockFilter::ja(0); libc::BPF_MAXINSNS as usize];
+        insns.extend([
+            bpf::SockFilter::ld_len(),
+            bpf::SockFilter::jeq(60, 0, 3),
+            bpf::SockFilter::ldb(59),
+            bpf::SockFilter::jeq(0xb5, 0, 1),
+            bpf::SockFilter::ret(u32::MAX),
+            bpf::SockFilter::ret(0),
+        ]);
+        assert_eq!(my_sock.set_filter(bpf::Program::new(insns)).unwrap(), bpf::FilterMode::Userspace);
+
+        for marker in [0xb6, 0xb5, 0xb6, 0xb5, 0xb5] {
+            sender.write(&frame(protocol, marker)).await.unwrap();
+        }
+
+        let mut short_buf = [0u8; 16];
+        let (read_size, info) = my_sock.recv_from(&mut short_buf).await.unwrap();
+        assert_eq!((&short_buf[..read_size], info.frame_len()), (&frame(protocol, 0xb5)[..16], 60));
+
+        let mut bufs = vec![PacketBuf::new(16); 3];
+        assert_eq!(my_sock.read_batch(&mut bufs).await.unwrap(), 2);
+        for buf in &bufs[..2] {
+            assert_eq!(buf.data(), &frame(protocol, 0xb5)[..16]);
+            assert!(buf.is_truncated());
+        }
+    }
 }
@@ -0,0 +1,81 @@
+use std::{ffi::c_int, fmt, io, os::fd::AsRawFd};
+
+use crate::{addr::MacAddr, sys, RawSock};
+
+/// What a socket asks its interface to receive beyond frames addressed to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Membership {
+    /// Every frame on the wire (`PACKET_MR_PROMISC`)
+    Promisc,
+    /// Every multicast frame (`PACKET_MR_ALLMULTI`)
+    AllMulti,
+    /// Frames sent to one multicast group address (`PACKET_MR_MULTICAST`)
+    Multicast(MacAddr),
+}
+
+impl Membership {
+    fn request(&self) -> (c_int, &[u8]) {
+        match self {
+            Membership::Promisc => (libc::PACKET_MR_PROMISC, &[]),
+            Membership::AllMulti => (libc::PACKET_MR_ALLMULTI, &[]),
+            Membership::Multicast(addr) => (libc::PACKET_MR_MULTICAST, &addr.0),
+        }
+    }
+}
+
+impl fmt::Display for Membership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Membership::Promisc => f.write_str("promiscuous mode"),
+            Membership::AllMulti => f.write_str("all multicast"),
+            Membership::Multicast(addr) => write!(f, "multicast group {addr}"),
+        }
+    }
+}
+
+/// A membership added by [`RawSock::add_membership`], dropped again along with the guard.
+///
+/// The kernel counts memberships per interface and drops a socket's own when it closes, so
+/// [`std::mem::forget`]ting the guard keeps the membership for the socket's lifetime but never
+/// beyond it
+#[must_use = "the membership is dropped along with the guard"]
+pub struct MembershipGuard<'a> {
+    sock: &'a RawSock,
+    ifindex: c_int,
+    membership: Membership,
+}
+
+impl MembershipGuard<'_> {
+    pub fn membership(&self) -> Membership {
+        self.membership
+    }
+
+    /// Index of the interface the membership is on
+    pub fn ifindex(&self) -> u32 {
+        self.ifindex as u32
+    }
+}
+
+impl Drop for MembershipGuard<'_> {
+    fn drop(&mut self) {
+        let (mr_type, addr) = self.membership.request();
+        let _ = sys::packet_membership(self.sock.fd.as_raw_fd(), libc::PACKET_DROP_MEMBERSHIP, self.ifindex, mr_type, addr);
+    }
+}
+
+impl RawSock {
+    /// Adds a membership (`PACKET_ADD_MEMBERSHIP`) on the interface the socket is bound to,
+    /// which lasts until the returned guard is dropped or the socket is closed
+    pub fn add_membership(&self, membership: Membership) -> io::Result<MembershipGuard<'_>> {
+        let fd = self.fd.as_raw_fd();
+        let ifindex = sys::local_addr(fd)?.sll_ifindex;
+        if ifindex == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "memberships need a socket bound to an interface"));
+        }
+
+        let (mr_type, addr) = membership.request();
+        sys::packet_membership(fd, libc::PACKET_ADD_MEMBERSHIP, ifindex, mr_type, addr)?;
+
+        Ok(MembershipGuard { sock: self, ifindex, membership })
+    }
+}
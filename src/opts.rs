@@ -0,0 +1,456 @@
+use std::{error::Error, ffi::{c_int, c_uint, CString}, fmt, io, os::fd::RawFd, ptr};
+
+use crate::{addr::MacAddr, bpf::SockFilter, ethertype::EtherType};
+
+/// Validated options for creating a [`RawSock`](crate::RawSock). Build with [`SockOpts::builder`]
+#[derive(Debug, Clone)]
+pub struct SockOpts {
+    /// The ethernet protocol type to bind this socket to
+    pub(crate) protocol: EtherType,
+    pub(crate) kind: SocketKind,
+    /// The resolved index of the interface to bind this raw socket to, or 0 for every interface
+    pub(crate) ifindex: c_int,
+    pub(crate) recv_buf: Option<c_int>,
+    pub(crate) send_buf: Option<c_int>,
+    pub(crate) promiscuous: bool,
+    pub(crate) ignore_outgoing: bool,
+    pub(crate) fanout: Option<Fanout>,
+    pub(crate) rx_timestamp: Option<RxTimestamp>,
+    pub(crate) tx_timestamps: bool,
+    pub(crate) auxdata: bool,
+    pub(crate) reinsert_vlan: bool,
+}
+
+impl SockOpts {
+    pub fn builder() -> SockOptsBuilder {
+        SockOptsBuilder::default()
+    }
+
+    /// The index of the interface this socket will be bound to, if any
+    pub fn ifindex(&self) -> Option<u32> {
+        (self.ifindex > 0).then_some(self.ifindex as u32)
+    }
+
+    /// The `SO_TIMESTAMPING` flags for the requested timestamps, 0 if none are
+    pub(crate) fn timestamping(&self) -> c_uint {
+        let mut flags = 0;
+
+        if self.rx_timestamp == Some(RxTimestamp::Software) {
+            flags |= libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE;
+        }
+
+        // Only the timestamp is wanted back, not a copy of the frame
+        if self.tx_timestamps {
+            flags |= libc::SOF_TIMESTAMPING_TX_SOFTWARE
+                | libc::SOF_TIMESTAMPING_SOFTWARE
+                | libc::SOF_TIMESTAMPING_OPT_ID
+                | libc::SOF_TIMESTAMPING_OPT_TSONLY;
+        }
+
+        flags
+    }
+}
+
+/// Whether the socket works with whole frames or with the link-layer header handled by the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SocketKind {
+    /// `SOCK_RAW`: frames are read and written including their link-layer header
+    #[default]
+    Raw,
+    /// `SOCK_DGRAM`: the link-layer header is stripped on receive, with the source available
+    /// through [`RawSock::recv_from`](crate::RawSock::recv_from), and built by the kernel from the
+    /// destination given to [`RawSock::send_to`](crate::RawSock::send_to)
+    Cooked,
+}
+
+/// How the kernel timestamps received frames, for [`PacketInfo::timestamp`](crate::PacketInfo::timestamp)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RxTimestamp {
+    /// `SO_TIMESTAMPNS`: the time the frame entered the stack, with nanosecond resolution
+    Ns,
+    /// `SO_TIMESTAMPING` with `SOF_TIMESTAMPING_RX_SOFTWARE`: the same software timestamp through
+    /// the timestamping interface, which also carries hardware timestamps
+    Software,
+}
+
+/// How a `PACKET_FANOUT` group picks the socket each frame goes to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FanoutMode {
+    /// By flow hash, so a flow always lands on the same socket
+    Hash,
+    /// Round-robin
+    Lb,
+    /// By the CPU the frame arrived on
+    Cpu,
+    /// Fill one socket until its queue is full, then move on to the next
+    Rollover,
+    Random,
+    /// By the NIC receive queue the frame arrived on
+    Qm,
+    /// By the return value of a classic BPF program, modulo the group size
+    Cbpf(Vec<SockFilter>),
+    /// By the return value of an eBPF program loaded by the caller, modulo the group size.
+    /// The fd only needs to stay open until the socket is created
+    Ebpf(RawFd),
+}
+
+/// Membership of a `PACKET_FANOUT` group, which splits the frames the group receives across its sockets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fanout {
+    id: u16,
+    mode: FanoutMode,
+    flags: c_uint,
+}
+
+impl Fanout {
+    /// Joins or creates the group `id`. Every socket in a group must use the same mode and flags
+    pub fn new(id: u16, mode: FanoutMode) -> Self {
+        Self { id, mode, flags: 0 }
+    }
+
+    /// Move frames on to another socket when the chosen one is full (`PACKET_FANOUT_FLAG_ROLLOVER`)
+    pub fn rollover(self, on: bool) -> Self {
+        self.flag(libc::PACKET_FANOUT_FLAG_ROLLOVER, on)
+    }
+
+    /// Reassemble IP fragments before hashing, so they all land on the same socket (`PACKET_FANOUT_FLAG_DEFRAG`)
+    pub fn defrag(self, on: bool) -> Self {
+        self.flag(libc::PACKET_FANOUT_FLAG_DEFRAG, on)
+    }
+
+    /// Skip frames sent from this host (`PACKET_FANOUT_FLAG_IGNORE_OUTGOING`)
+    pub fn ignore_outgoing(self, on: bool) -> Self {
+        self.flag(libc::PACKET_FANOUT_FLAG_IGNORE_OUTGOING, on)
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn mode(&self) -> &FanoutMode {
+        &self.mode
+    }
+
+    fn flag(mut self, flag: c_uint, on: bool) -> Self {
+        if on { self.flags |= flag } else { self.flags &= !flag }
+        self
+    }
+
+    /// The `PACKET_FANOUT` option value: group id in the low 16 bits, mode and flags in the high
+    pub(crate) fn arg(&self) -> c_int {
+        let mode = match self.mode {
+            FanoutMode::Hash => libc::PACKET_FANOUT_HASH,
+            FanoutMode::Lb => libc::PACKET_FANOUT_LB,
+            FanoutMode::Cpu => libc::PACKET_FANOUT_CPU,
+            FanoutMode::Rollover => libc::PACKET_FANOUT_ROLLOVER,
+            FanoutMode::Random => libc::PACKET_FANOUT_RND,
+            FanoutMode::Qm => libc::PACKET_FANOUT_QM,
+            FanoutMode::Cbpf(_) => libc::PACKET_FANOUT_CBPF,
+            FanoutMode::Ebpf(_) => libc::PACKET_FANOUT_EBPF,
+        };
+
+        (self.id as c_uint | (mode | self.flags) << 16) as c_int
+    }
+}
+
+/// The ways an interface can be selected for a [`SockOpts`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Interface {
+    Name(String),
+    Index(u32),
+    Mac(MacAddr),
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interface::Name(name) => write!(f, "{name:?}"),
+            Interface::Index(index) => write!(f, "ifindex {index}"),
+            Interface::Mac(mac) => write!(f, "mac {mac}"),
+        }
+    }
+}
+
+/// Builder for [`SockOpts`]. Nothing is checked until [`SockOptsBuilder::build`].
+///
+/// Without an interface the socket is left unbound: it receives from every interface and can only
+/// transmit with [`RawSock::send_to`](crate::RawSock::send_to)
+#[derive(Debug, Clone)]
+pub struct SockOptsBuilder {
+    protocol: EtherType,
+    kind: SocketKind,
+    intf: Option<Interface>,
+    recv_buf: Option<usize>,
+    send_buf: Option<usize>,
+    promiscuous: bool,
+    ignore_outgoing: bool,
+    fanout: Option<Fanout>,
+    rx_timestamp: Option<RxTimestamp>,
+    tx_timestamps: bool,
+    auxdata: bool,
+    reinsert_vlan: bool,
+}
+
+impl Default for SockOptsBuilder {
+    fn default() -> Self {
+        Self {
+            protocol: EtherType::ALL,
+            kind: SocketKind::Raw,
+            intf: None,
+            recv_buf: None,
+            send_buf: None,
+            promiscuous: false,
+            ignore_outgoing: false,
+            fanout: None,
+            rx_timestamp: None,
+            tx_timestamps: false,
+            auxdata: false,
+            reinsert_vlan: false,
+        }
+    }
+}
+
+impl SockOptsBuilder {
+    /// The ethernet protocol type to bind to. Defaults to [`EtherType::ALL`]
+    pub fn protocol(mut self, protocol: EtherType) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Raw or cooked packet socket. Defaults to [`SocketKind::Raw`]
+    pub fn kind(mut self, kind: SocketKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Select the interface by name, e.g. `"eth0"`
+    pub fn interface(mut self, name: &str) -> Self {
+        self.intf = Some(Interface::Name(name.to_owned()));
+        self
+    }
+
+    /// Select the interface by its kernel index
+    pub fn ifindex(mut self, index: u32) -> Self {
+        self.intf = Some(Interface::Index(index));
+        self
+    }
+
+    /// Select the interface owning the given hardware address
+    pub fn mac(mut self, mac: MacAddr) -> Self {
+        self.intf = Some(Interface::Mac(mac));
+        self
+    }
+
+    /// Size of the kernel receive buffer (`SO_RCVBUF`)
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buf = Some(size);
+        self
+    }
+
+    /// Size of the kernel send buffer (`SO_SNDBUF`)
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buf = Some(size);
+        self
+    }
+
+    /// Put the interface into promiscuous mode for as long as the socket is open. Requires an interface
+    pub fn promiscuous(mut self, on: bool) -> Self {
+        self.promiscuous = on;
+        self
+    }
+
+    /// Only receive incoming frames, skipping the copies of frames sent from this host
+    /// (`PACKET_IGNORE_OUTGOING`)
+    pub fn ignore_outgoing(mut self, on: bool) -> Self {
+        self.ignore_outgoing = on;
+        self
+    }
+
+    /// Join a `PACKET_FANOUT` group once bound. See also [`RawSock::fanout_group`](crate::RawSock::fanout_group)
+    pub fn fanout(mut self, fanout: Fanout) -> Self {
+        self.fanout = Some(fanout);
+        self
+    }
+
+    /// Timestamp received frames, returned by [`RawSock::recv_from`](crate::RawSock::recv_from)
+    pub fn rx_timestamp(mut self, mode: RxTimestamp) -> Self {
+        self.rx_timestamp = Some(mode);
+        self
+    }
+
+    /// Timestamp sent frames as they are handed to the device, read back with
+    /// [`RawSock::tx_timestamps`](crate::RawSock::tx_timestamps)
+    pub fn tx_timestamps(mut self, on: bool) -> Self {
+        self.tx_timestamps = on;
+        self
+    }
+
+    /// Attach a `tpacket_auxdata` to every received frame (`PACKET_AUXDATA`), returned by
+    /// [`RawSock::recv_from`](crate::RawSock::recv_from) as [`PacketInfo::aux`](crate::PacketInfo::aux)
+    pub fn auxdata(mut self, on: bool) -> Self {
+        self.auxdata = on;
+        self
+    }
+
+    /// Put the 802.1Q tag the NIC or the kernel stripped off a received frame back into the bytes
+    /// read, so the frame looks as it did on the wire. Turns on [`SockOptsBuilder::auxdata`],
+    /// and has no effect on cooked sockets
+    pub fn reinsert_vlan(mut self, on: bool) -> Self {
+        self.reinsert_vlan = on;
+        self
+    }
+
+    pub fn build(self) -> Result<SockOpts, OptsError> {
+        let ifindex = match self.intf {
+            Some(intf) => resolve(intf)?,
+            None if self.promiscuous => return Err(OptsError::MissingInterface),
+            None => 0,
+        };
+
+        if let Some(Fanout { mode: FanoutMode::Cbpf(program), .. }) = &self.fanout
+            && (program.is_empty() || program.len() > libc::BPF_MAXINSNS as usize)
+        {
+            return Err(OptsError::InvalidFanoutProgram);
+        }
+
+        Ok(SockOpts {
+            protocol: self.protocol,
+            kind: self.kind,
+            ifindex,
+            recv_buf: self.recv_buf.map(buffer_size).transpose()?,
+            send_buf: self.send_buf.map(buffer_size).transpose()?,
+            promiscuous: self.promiscuous,
+            ignore_outgoing: self.ignore_outgoing,
+            fanout: self.fanout,
+            rx_timestamp: self.rx_timestamp,
+            tx_timestamps: self.tx_timestamps,
+            auxdata: self.auxdata || self.reinsert_vlan,
+            reinsert_vlan: self.reinsert_vlan,
+        })
+    }
+}
+
+fn buffer_size(size: usize) -> Result<c_int, OptsError> {
+    match c_int::try_from(size) {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(OptsError::InvalidBufferSize(size)),
+    }
+}
+
+fn resolve(intf: Interface) -> Result<c_int, OptsError> {
+    let index = match &intf {
+        Interface::Name(name) => {
+            if name.len() >= libc::IFNAMSIZ {
+                return Err(OptsError::NameTooLong(name.len()));
+            }
+
+            let name = CString::new(name.as_str()).map_err(|_| OptsError::NulInName)?;
+            unsafe { libc::if_nametoindex(name.as_ptr()) }
+        }
+        Interface::Index(index) => {
+            let mut name = [0; libc::IFNAMSIZ];
+            let found = *index != 0 && !unsafe { libc::if_indextoname(*index, name.as_mut_ptr()) }.is_null();
+            if found { *index } else { 0 }
+        }
+        Interface::Mac(mac) => index_of_mac(mac),
+    };
+
+    match c_int::try_from(index) {
+        Ok(index) if index > 0 => Ok(index),
+        _ => Err(OptsError::UnknownInterface(intf)),
+    }
+}
+
+/// Walks the `AF_PACKET` entries of `getifaddrs` looking for a matching hardware address
+fn index_of_mac(mac: &MacAddr) -> u32 {
+    let mut found = 0;
+
+    unsafe {
+        let mut addrs = ptr::null_mut();
+        if libc::getifaddrs(&mut addrs) < 0 {
+            return 0;
+        }
+
+        let mut cur = addrs;
+        while !cur.is_null() {
+            let addr = (*cur).ifa_addr;
+            if !addr.is_null() && (*addr).sa_family as c_int == libc::AF_PACKET {
+                let ll = &*(addr as *const libc::sockaddr_ll);
+                if ll.sll_halen == 6 && ll.sll_addr[..6] == mac.0 {
+                    found = ll.sll_ifindex as u32;
+                    break;
+                }
+            }
+            cur = (*cur).ifa_next;
+        }
+
+        libc::freeifaddrs(addrs);
+    }
+
+    found
+}
+
+/// Reasons a [`SockOptsBuilder`] can be rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptsError {
+    /// An option that needs an interface was set without selecting one
+    MissingInterface,
+    /// The interface name contains a NUL byte
+    NulInName,
+    /// The interface name is this many bytes long, which does not fit in `IFNAMSIZ`
+    NameTooLong(usize),
+    /// No interface matched the selection
+    UnknownInterface(Interface),
+    /// A buffer size was zero or does not fit in a `c_int`
+    InvalidBufferSize(usize),
+    /// A `PACKET_FANOUT_CBPF` program was empty or longer than `BPF_MAXINSNS`
+    InvalidFanoutProgram,
+}
+
+impl fmt::Display for OptsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptsError::MissingInterface => write!(f, "no interface selected for an option that requires one"),
+            OptsError::NulInName => write!(f, "invalid interface name - contains a NUL byte"),
+            OptsError::NameTooLong(len) => write!(f, "invalid interface name - {len} bytes exceeds length"),
+            OptsError::UnknownInterface(intf) => write!(f, "no such interface: {intf}"),
+            OptsError::InvalidBufferSize(size) => write!(f, "invalid buffer size {size}"),
+            OptsError::InvalidFanoutProgram => write!(f, "invalid fanout program length"),
+        }
+    }
+}
+
+impl Error for OptsError {}
+
+impl From<OptsError> for io::Error {
+    fn from(err: OptsError) -> Self {
+        let kind = match err {
+            OptsError::UnknownInterface(_) => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::InvalidInput,
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        let err = |b: SockOptsBuilder| b.build().unwrap_err();
+
+        assert_eq!(err(SockOpts::builder().promiscuous(true)), OptsError::MissingInterface);
+        assert_eq!(err(SockOpts::builder().interface("l\0o")), OptsError::NulInName);
+        assert_eq!(err(SockOpts::builder().interface("a-very-long-intf-name")), OptsError::NameTooLong(21));
+        assert!(matches!(err(SockOpts::builder().interface("nosuchif0")), OptsError::UnknownInterface(_)));
+        assert!(matches!(err(SockOpts::builder().ifindex(0)), OptsError::UnknownInterface(_)));
+        assert!(matches!(err(SockOpts::builder().mac(MacAddr([0x02, 0, 0, 0, 0, 0x42]))), OptsError::UnknownInterface(_)));
+        assert_eq!(err(SockOpts::builder().interface("lo").recv_buffer_size(0)), OptsError::InvalidBufferSize(0));
+
+        let lo = SockOpts::builder().interface("lo").build().unwrap();
+        assert_eq!(SockOpts::builder().ifindex(lo.ifindex().unwrap()).build().unwrap().ifindex, lo.ifindex);
+        assert_eq!(SockOpts::builder().build().unwrap().ifindex(), None);
+    }
+}
@@ -0,0 +1,295 @@
+use std::{error::Error, fmt, time::{Duration, SystemTime}};
+
+use crate::{addr::MacAddr, ethertype::EtherType, sys::MsgMeta};
+
+/// Where a received frame was headed, from `sll_pkttype`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketType {
+    /// Addressed to this host
+    Host,
+    Broadcast,
+    Multicast,
+    /// Addressed to another host, seen because the interface is promiscuous
+    OtherHost,
+    /// Sent from this host, looped back to packet sockets
+    Outgoing,
+    /// Any value the kernel may add in the future
+    Other(u8),
+}
+
+impl From<u8> for PacketType {
+    fn from(value: u8) -> Self {
+        match value {
+            libc::PACKET_HOST => PacketType::Host,
+            libc::PACKET_BROADCAST => PacketType::Broadcast,
+            libc::PACKET_MULTICAST => PacketType::Multicast,
+            libc::PACKET_OTHERHOST => PacketType::OtherHost,
+            libc::PACKET_OUTGOING => PacketType::Outgoing,
+            other => PacketType::Other(other),
+        }
+    }
+}
+
+impl From<PacketType> for u8 {
+    fn from(pkt_type: PacketType) -> Self {
+        match pkt_type {
+            PacketType::Host => libc::PACKET_HOST,
+            PacketType::Broadcast => libc::PACKET_BROADCAST,
+            PacketType::Multicast => libc::PACKET_MULTICAST,
+            PacketType::OtherHost => libc::PACKET_OTHERHOST,
+            PacketType::Outgoing => libc::PACKET_OUTGOING,
+            PacketType::Other(other) => other,
+        }
+    }
+}
+
+/// Link-layer metadata for a received frame, from the `sockaddr_ll` filled in by `recvfrom`
+/// and any control messages the socket options turned on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketInfo {
+    ifindex: u32,
+    pkt_type: PacketType,
+    hatype: u16,
+    protocol: EtherType,
+    addr: [u8; 8],
+    addr_len: u8,
+    frame_len: usize,
+    timestamp: Option<SystemTime>,
+    aux: Option<AuxData>,
+}
+
+impl PacketInfo {
+    pub(crate) fn from_sockaddr(addr: &libc::sockaddr_ll, frame_len: usize) -> Self {
+        Self {
+            ifindex: addr.sll_ifindex as u32,
+            pkt_type: PacketType::from(addr.sll_pkttype),
+            hatype: addr.sll_hatype,
+            protocol: EtherType::from_network(addr.sll_protocol),
+            addr: addr.sll_addr,
+            addr_len: addr.sll_halen.min(8),
+            frame_len,
+            timestamp: None,
+            aux: None,
+        }
+    }
+
+    /// The metadata of a frame received with `MSG_TRUNC`, whose full length is `frame_len`
+    pub(crate) fn from_msg(meta: &MsgMeta, frame_len: usize) -> Self {
+        let mut info = Self::from_sockaddr(&meta.addr, frame_len);
+
+        for (level, ty, data) in meta.cmsgs() {
+            match (level, ty) {
+                // `SCM_TIMESTAMPING` has the software timestamp first, followed by the deprecated
+                // and hardware ones
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS | libc::SCM_TIMESTAMPING) => info.timestamp = timestamp(data),
+                (libc::SOL_PACKET, libc::PACKET_AUXDATA) => info.aux = AuxData::parse(data),
+                _ => {}
+            }
+        }
+
+        info
+    }
+
+    pub(crate) fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Index of the interface the frame was received on
+    pub fn ifindex(&self) -> u32 {
+        self.ifindex
+    }
+
+    pub fn pkt_type(&self) -> PacketType {
+        self.pkt_type
+    }
+
+    /// The ARP hardware type of the interface, e.g. [`libc::ARPHRD_ETHER`]
+    pub fn hatype(&self) -> u16 {
+        self.hatype
+    }
+
+    pub fn protocol(&self) -> EtherType {
+        self.protocol
+    }
+
+    /// The source hardware address, as long as the interface's hardware addresses are
+    pub fn addr(&self) -> &[u8] {
+        &self.addr[..self.addr_len as usize]
+    }
+
+    /// The source hardware address, if it is a 6 byte MAC address
+    pub fn mac(&self) -> Option<MacAddr> {
+        self.addr().try_into().ok().map(MacAddr)
+    }
+
+    /// The length of the whole frame, which is more than was read if the buffer was too small
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    /// Cuts the frame down to the `len` bytes a socket filter kept, as the kernel trims it
+    pub(crate) fn trim(&mut self, len: usize) {
+        self.frame_len = self.frame_len.min(len);
+    }
+
+    /// When the kernel received the frame, if the socket was opened with an
+    /// [`RxTimestamp`](crate::RxTimestamp) mode
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.timestamp
+    }
+
+    /// The frame's `tpacket_auxdata`, if the socket was opened with
+    /// [`SockOptsBuilder::auxdata`](crate::SockOptsBuilder::auxdata)
+    pub fn aux(&self) -> Option<&AuxData> {
+        self.aux.as_ref()
+    }
+}
+
+/// Whether the kernel vouches for a received frame's transport checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumStatus {
+    /// Nothing is known, so the checksum needs checking
+    Unknown,
+    /// The checksum has not been filled in yet because it is left to the NIC, as for frames sent
+    /// from this host (`TP_STATUS_CSUMNOTREADY`)
+    NotReady,
+    /// Already verified, e.g. by the NIC (`TP_STATUS_CSUM_VALID`)
+    Valid,
+}
+
+/// What the kernel knows about a received frame beyond its bytes, from `tpacket_auxdata`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AuxData {
+    status: u32,
+    len: u32,
+    snaplen: u32,
+    vlan_tci: u16,
+    vlan_tpid: u16,
+}
+
+impl AuxData {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let aux = data.get(..std::mem::size_of::<libc::tpacket_auxdata>())?;
+        let aux = unsafe { (aux.as_ptr() as *const libc::tpacket_auxdata).read_unaligned() };
+
+        Some(Self {
+            status: aux.tp_status,
+            len: aux.tp_len,
+            snaplen: aux.tp_snaplen,
+            vlan_tci: aux.tp_vlan_tci,
+            vlan_tpid: aux.tp_vlan_tpid,
+        })
+    }
+
+    /// The raw `TP_STATUS_*` flags
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    /// The length of the frame as received, before any truncation by a filter or the read buffer
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How much of the frame the socket's filter let through
+    pub fn snaplen(&self) -> usize {
+        self.snaplen as usize
+    }
+
+    /// The TCI of the 802.1Q tag stripped from the frame, if there was one
+    pub fn vlan_tci(&self) -> Option<u16> {
+        (self.status & libc::TP_STATUS_VLAN_VALID != 0).then_some(self.vlan_tci)
+    }
+
+    /// The TPID of the stripped tag, e.g. 0x88a8 for 802.1ad. Kernels that do not report it only
+    /// strip 802.1Q tags
+    pub fn vlan_tpid(&self) -> Option<EtherType> {
+        match self.status & (libc::TP_STATUS_VLAN_VALID | libc::TP_STATUS_VLAN_TPID_VALID) {
+            0 => None,
+            libc::TP_STATUS_VLAN_VALID => Some(EtherType::VLAN),
+            _ => Some(EtherType::new(self.vlan_tpid)),
+        }
+    }
+
+    pub fn checksum(&self) -> ChecksumStatus {
+        if self.status & libc::TP_STATUS_CSUMNOTREADY != 0 {
+            ChecksumStatus::NotReady
+        } else if self.status & libc::TP_STATUS_CSUM_VALID != 0 {
+            ChecksumStatus::Valid
+        } else {
+            ChecksumStatus::Unknown
+        }
+    }
+}
+
+/// Puts the stripped VLAN tag back after the MAC addresses of the `len` byte frame in `buf`,
+/// returning the new length and counting the tag in the frame length of `info`. The end of the
+/// frame is cut off if `buf` has no room for the tag
+pub(crate) fn reinsert_vlan(buf: &mut [u8], len: usize, info: &mut PacketInfo) -> usize {
+    let Some(aux) = info.aux() else {
+        return len;
+    };
+    let (Some(tci), Some(tpid)) = (aux.vlan_tci(), aux.vlan_tpid()) else {
+        return len;
+    };
+    if len < 12 || buf.len() < 16 {
+        return len;
+    }
+
+    let len = (len + 4).min(buf.len());
+    buf.copy_within(12..len - 4, 16);
+    buf[12..14].copy_from_slice(&tpid.value().to_be_bytes());
+    buf[14..16].copy_from_slice(&tci.to_be_bytes());
+    info.frame_len += 4;
+    len
+}
+
+/// The error from [`RawSock::read_exact_frame`](crate::RawSock::read_exact_frame) when a frame
+/// did not fit the buffer, carried by an [`io::Error`](std::io::Error) of kind `InvalidData`.
+/// The frame is consumed all the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TruncatedFrame {
+    len: usize,
+    capacity: usize,
+}
+
+impl TruncatedFrame {
+    pub(crate) fn new(len: usize, capacity: usize) -> Self {
+        Self { len, capacity }
+    }
+
+    /// The length of the whole frame
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The size of the buffer it was read into
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl fmt::Display for TruncatedFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} byte frame truncated to fit a {} byte buffer", self.len, self.capacity)
+    }
+}
+
+impl Error for TruncatedFrame {}
+
+/// Reads the `timespec` at the start of a timestamp control message, which is all zeroes if unset
+pub(crate) fn timestamp(data: &[u8]) -> Option<SystemTime> {
+    let ts = data.get(..std::mem::size_of::<libc::timespec>())?;
+    let ts = unsafe { (ts.as_ptr() as *const libc::timespec).read_unaligned() };
+
+    (ts.tv_sec != 0 || ts.tv_nsec != 0).then(|| SystemTime::UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
@@ -0,0 +1,188 @@
+//! The runtime a socket waits for readiness on: tokio's `AsyncFd` with the `tokio` feature, or
+//! else async-io's `Async`, which also drives smol. The rest of the crate only goes through
+//! [`Fd`], running its non-blocking calls as closures over the raw descriptor
+
+use std::os::fd::{AsRawFd, RawFd};
+
+#[cfg(not(any(feature = "tokio", feature = "async-io")))]
+compile_error!("async-raw needs a runtime: enable the `tokio` or `async-io` feature");
+
+#[cfg(feature = "tokio")]
+mod imp {
+    use std::{io, os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd}, task::{ready, Context, Poll}};
+
+    use tokio::io::{unix::AsyncFd, Interest, Ready};
+
+    #[derive(Debug)]
+    pub(crate) struct Fd(AsyncFd<OwnedFd>);
+
+    impl Fd {
+        pub(crate) fn new(fd: OwnedFd) -> io::Result<Self> {
+            // Registering panics outside a runtime, so that is turned into an error first
+            tokio::runtime::Handle::try_current().map_err(|_| io::Error::other("no tokio reactor running"))?;
+
+            // Error readiness signals transmit timestamps waiting on the error queue. The OwnedFd
+            // keeps the descriptor open for as long as the AsyncFd holds it
+            Ok(Self(unsafe { AsyncFd::register_with_interest(fd, Interest::READABLE | Interest::WRITABLE | Interest::ERROR)? }))
+        }
+
+        /// Readiness is cleared whenever `op` would block, so a stale wakeup parks the task
+        /// again instead of spinning
+        pub(crate) fn poll_read_io<R>(&self, cx: &mut Context<'_>, mut op: impl FnMut(RawFd) -> io::Result<R>) -> Poll<io::Result<R>> {
+            loop {
+                let mut guard = ready!(self.0.poll_read_ready(cx))?;
+                if let Ok(res) = guard.try_io(|fd| op(fd.as_raw_fd())) {
+                    return Poll::Ready(res);
+                }
+            }
+        }
+
+        pub(crate) fn poll_write_io<R>(&self, cx: &mut Context<'_>, mut op: impl FnMut(RawFd) -> io::Result<R>) -> Poll<io::Result<R>> {
+            loop {
+                let mut guard = ready!(self.0.poll_write_ready(cx))?;
+                if let Ok(res) = guard.try_io(|fd| op(fd.as_raw_fd())) {
+                    return Poll::Ready(res);
+                }
+            }
+        }
+
+        pub(crate) async fn error_io<R>(&self, mut op: impl FnMut(RawFd) -> io::Result<R>) -> io::Result<R> {
+            loop {
+                let mut guard = self.0.ready(Interest::ERROR).await?;
+                match op(guard.get_ref().as_raw_fd()) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => guard.clear_ready_matching(Ready::ERROR),
+                    res => return res,
+                }
+            }
+        }
+
+        pub(crate) fn try_read_io<R>(&self, op: impl FnOnce(RawFd) -> io::Result<R>) -> io::Result<R> {
+            self.0.try_io(Interest::READABLE, |fd| op(fd.as_raw_fd()))
+        }
+
+        pub(crate) fn try_write_io<R>(&self, op: impl FnOnce(RawFd) -> io::Result<R>) -> io::Result<R> {
+            self.0.try_io(Interest::WRITABLE, |fd| op(fd.as_raw_fd()))
+        }
+
+        pub(crate) async fn readable(&self) -> io::Result<()> {
+            self.0.readable().await.map(drop)
+        }
+
+        pub(crate) async fn writable(&self) -> io::Result<()> {
+            self.0.writable().await.map(drop)
+        }
+
+        pub(crate) fn as_fd(&self) -> BorrowedFd<'_> {
+            self.0.get_ref().as_fd()
+        }
+
+        pub(crate) fn into_inner(self) -> OwnedFd {
+            self.0.into_inner()
+        }
+    }
+}
+
+#[cfg(all(feature = "async-io", not(feature = "tokio")))]
+mod imp {
+    use std::{io, os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd}, sync::OnceLock, task::{ready, Context, Poll}};
+
+    use async_io::Async;
+
+    #[derive(Debug)]
+    pub(crate) struct Fd {
+        io: Async<OwnedFd>,
+        errors: OnceLock<Async<OwnedFd>>,
+    }
+
+    impl Fd {
+        pub(crate) fn new(fd: OwnedFd) -> io::Result<Self> {
+            Ok(Self { io: Async::new_nonblocking(fd)?, errors: OnceLock::new() })
+        }
+
+        /// async-io has no error readiness, so the socket is also watched by an epoll instance of
+        /// its own that asks for no events: only `EPOLLERR`, which is always reported, makes it
+        /// readable. It is made on first use
+        fn errors(&self) -> io::Result<&Async<OwnedFd>> {
+            if let Some(errors) = self.errors.get() {
+                return Ok(errors);
+            }
+
+            let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+            if epfd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let epfd = unsafe { OwnedFd::from_raw_fd(epfd) };
+
+            let mut event = libc::epoll_event { events: 0, u64: 0 };
+            if unsafe { libc::epoll_ctl(epfd.as_raw_fd(), libc::EPOLL_CTL_ADD, self.io.as_raw_fd(), &mut event) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let errors = Async::new(epfd)?;
+            Ok(self.errors.get_or_init(|| errors))
+        }
+
+        /// async-io only reports readiness the OS delivered since the task last parked, so `op`
+        /// runs first and the task parks once it would block
+        pub(crate) fn poll_read_io<R>(&self, cx: &mut Context<'_>, mut op: impl FnMut(RawFd) -> io::Result<R>) -> Poll<io::Result<R>> {
+            loop {
+                match op(self.io.as_raw_fd()) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => ready!(self.io.poll_readable(cx))?,
+                    res => return Poll::Ready(res),
+                }
+            }
+        }
+
+        pub(crate) fn poll_write_io<R>(&self, cx: &mut Context<'_>, mut op: impl FnMut(RawFd) -> io::Result<R>) -> Poll<io::Result<R>> {
+            loop {
+                match op(self.io.as_raw_fd()) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => ready!(self.io.poll_writable(cx))?,
+                    res => return Poll::Ready(res),
+                }
+            }
+        }
+
+        pub(crate) async fn error_io<R>(&self, mut op: impl FnMut(RawFd) -> io::Result<R>) -> io::Result<R> {
+            let errors = self.errors()?;
+            loop {
+                match op(self.io.as_raw_fd()) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => errors.readable().await?,
+                    res => return res,
+                }
+            }
+        }
+
+        pub(crate) fn try_read_io<R>(&self, op: impl FnOnce(RawFd) -> io::Result<R>) -> io::Result<R> {
+            op(self.io.as_raw_fd())
+        }
+
+        pub(crate) fn try_write_io<R>(&self, op: impl FnOnce(RawFd) -> io::Result<R>) -> io::Result<R> {
+            op(self.io.as_raw_fd())
+        }
+
+        pub(crate) async fn readable(&self) -> io::Result<()> {
+            self.io.readable().await
+        }
+
+        pub(crate) async fn writable(&self) -> io::Result<()> {
+            self.io.writable().await
+        }
+
+        pub(crate) fn as_fd(&self) -> BorrowedFd<'_> {
+            self.io.get_ref().as_fd()
+        }
+
+        pub(crate) fn into_inner(self) -> OwnedFd {
+            // Deregistering only fails if epoll lost track of the descriptor, which it never does
+            self.io.into_inner().expect("RawSock: failed to deregister the socket from async-io")
+        }
+    }
+}
+
+pub(crate) use imp::Fd;
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
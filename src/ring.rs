@@ -0,0 +1,54 @@
+use std::{io, os::fd::RawFd, ptr};
+
+mod rx;
+mod tx;
+
+pub use rx::{RxFrame, RxRing, RxRingOpts};
+pub use tx::{FlushReport, TxRing, TxRingOpts, TxSlot};
+
+/// A `PACKET_MMAP` ring shared with the kernel, unmapped on drop
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is plain memory; all access to it is synchronised through the block/frame status words
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error())
+        }
+
+        Ok(Self { ptr: ptr as *mut u8, len })
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,223 @@
+use std::{ffi::c_int, future::poll_fn, io, marker::PhantomData, os::fd::AsRawFd, sync::atomic::{AtomicU32, Ordering}, time::{Duration, SystemTime}};
+
+use crate::{sys, PacketInfo, PacketStats, RawSock};
+use super::{invalid, page_size, Mmap};
+
+/// Geometry of a TPACKET_V3 receive ring
+#[derive(Debug, Clone)]
+pub struct RxRingOpts {
+    block_size: u32,
+    block_count: u32,
+    frame_size: u32,
+    retire_timeout: Duration,
+}
+
+impl Default for RxRingOpts {
+    fn default() -> Self {
+        Self {
+            block_size: 1 << 20,
+            block_count: 16,
+            frame_size: 2048,
+            retire_timeout: Duration::from_millis(60),
+        }
+    }
+}
+
+impl RxRingOpts {
+    /// Size of each block in bytes. Must be a multiple of the page size
+    pub fn block_size(mut self, size: u32) -> Self {
+        self.block_size = size;
+        self
+    }
+
+    pub fn block_count(mut self, count: u32) -> Self {
+        self.block_count = count;
+        self
+    }
+
+    /// Nominal frame size, used by the kernel to bound the ring. TPACKET_V3 packs frames of
+    /// their actual size into blocks, so this is a minimum per-frame slot rather than a fixed stride
+    pub fn frame_size(mut self, size: u32) -> Self {
+        self.frame_size = size;
+        self
+    }
+
+    /// How long the kernel waits before handing over a block that is not yet full
+    pub fn retire_timeout(mut self, timeout: Duration) -> Self {
+        self.retire_timeout = timeout;
+        self
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if self.block_count == 0 {
+            return Err(invalid("ring block count must be non-zero"));
+        }
+
+        if self.block_size == 0 || !(self.block_size as usize).is_multiple_of(page_size()) {
+            return Err(invalid("ring block size must be a multiple of the page size"));
+        }
+
+        if (self.frame_size as usize) < libc::TPACKET3_HDRLEN
+            || !(self.frame_size as usize).is_multiple_of(libc::TPACKET_ALIGNMENT)
+            || self.frame_size > self.block_size
+        {
+            return Err(invalid("ring frame size must be aligned, hold a frame header and fit in a block"));
+        }
+
+        Ok(())
+    }
+}
+
+/// A `PACKET_RX_RING` of TPACKET_V3 blocks, mmapped so frames are read without a syscall each.
+///
+/// The kernel fills a block and hands it over once it is full or the retire timeout passes;
+/// [`RxRing::next_frame`] walks its frames and returns it to the kernel when moving on
+pub struct RxRing {
+    map: Mmap,
+    sock: RawSock,
+    block_size: usize,
+    block_count: usize,
+    block: usize,
+    /// Frames left and offset of the next one in the current block, while it belongs to us
+    cursor: Option<(u32, usize)>,
+}
+
+impl RxRing {
+    pub fn new(sock: RawSock, opts: RxRingOpts) -> io::Result<Self> {
+        opts.validate()?;
+
+        let fd = sock.fd.as_raw_fd();
+        let frames_per_block = opts.block_size / opts.frame_size;
+        let req = libc::tpacket_req3 {
+            tp_block_size: opts.block_size,
+            tp_block_nr: opts.block_count,
+            tp_frame_size: opts.frame_size,
+            tp_frame_nr: frames_per_block * opts.block_count,
+            tp_retire_blk_tov: opts.retire_timeout.as_millis().clamp(1, u32::MAX as u128) as u32,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+
+        sys::setsockopt(fd, libc::SOL_PACKET, libc::PACKET_VERSION, &(libc::tpacket_versions::TPACKET_V3 as c_int))?;
+        sys::setsockopt(fd, libc::SOL_PACKET, libc::PACKET_RX_RING, &req)?;
+
+        let block_size = opts.block_size as usize;
+        let block_count = opts.block_count as usize;
+
+        Ok(Self {
+            map: Mmap::new(fd, block_size * block_count)?,
+            sock,
+            block_size,
+            block_count,
+            block: 0,
+            cursor: None,
+        })
+    }
+
+    /// The kernel's counters for the ring's socket, including how often the ring froze
+    pub fn stats(&self) -> io::Result<PacketStats> {
+        self.sock.stats()
+    }
+
+    /// Waits for the next frame. The previous frame, and once exhausted its block, is given back
+    /// to the kernel by this call, which is why frames borrow the ring
+    pub async fn next_frame(&mut self) -> io::Result<RxFrame<'_>> {
+        loop {
+            if let Some((remaining, offset)) = self.cursor {
+                if remaining > 0 {
+                    return Ok(self.take_frame(remaining, offset));
+                }
+
+                self.release_block();
+            }
+
+            // The socket turns readable once the kernel retires a block to us
+            let cursor = poll_fn(|cx| self.sock.poll_read(cx, |_| self.user_block().ok_or(io::ErrorKind::WouldBlock.into()))).await?;
+            self.cursor = Some(cursor);
+        }
+    }
+
+    fn block_ptr(&self) -> *mut libc::tpacket_block_desc {
+        unsafe { self.map.ptr.add(self.block * self.block_size) as *mut libc::tpacket_block_desc }
+    }
+
+    fn block_status(&self) -> &AtomicU32 {
+        unsafe { AtomicU32::from_ptr(&raw mut (*self.block_ptr()).hdr.bh1.block_status) }
+    }
+
+    /// The frame count and first frame offset of the current block, if the kernel has retired it to us
+    fn user_block(&self) -> Option<(u32, usize)> {
+        if self.block_status().load(Ordering::Acquire) & libc::TP_STATUS_USER == 0 {
+            return None;
+        }
+
+        let hdr = unsafe { &(*self.block_ptr()).hdr.bh1 };
+        Some((hdr.num_pkts, hdr.offset_to_first_pkt as usize))
+    }
+
+    fn release_block(&mut self) {
+        self.block_status().store(libc::TP_STATUS_KERNEL, Ordering::Release);
+        self.block = (self.block + 1) % self.block_count;
+        self.cursor = None;
+    }
+
+    fn take_frame(&mut self, remaining: u32, offset: usize) -> RxFrame<'_> {
+        unsafe {
+            let base = (self.block_ptr() as *const u8).add(offset);
+            let hdr = &*(base as *const libc::tpacket3_hdr);
+            self.cursor = Some((remaining - 1, offset + hdr.tp_next_offset as usize));
+
+            RxFrame { base, _ring: PhantomData }
+        }
+    }
+}
+
+/// A frame borrowed from an [`RxRing`] block, along with its `tpacket3_hdr`
+pub struct RxFrame<'a> {
+    base: *const u8,
+    _ring: PhantomData<&'a RxRing>,
+}
+
+impl<'a> RxFrame<'a> {
+    pub fn header(&self) -> &'a libc::tpacket3_hdr {
+        unsafe { &*(self.base as *const libc::tpacket3_hdr) }
+    }
+
+    /// The captured bytes, starting at the link-layer header
+    pub fn data(&self) -> &'a [u8] {
+        let hdr = self.header();
+        unsafe { std::slice::from_raw_parts(self.base.add(hdr.tp_mac as usize), hdr.tp_snaplen as usize) }
+    }
+
+    /// The length of the frame on the wire, which may exceed [`RxFrame::data`] if it was truncated
+    pub fn len(&self) -> usize {
+        self.header().tp_len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// When the kernel received the frame
+    pub fn timestamp(&self) -> SystemTime {
+        let hdr = self.header();
+        SystemTime::UNIX_EPOCH + Duration::new(hdr.tp_sec as u64, hdr.tp_nsec)
+    }
+
+    /// The VLAN TCI, if the tag was stripped by the NIC
+    pub fn vlan_tci(&self) -> Option<u16> {
+        let hdr = self.header();
+        (hdr.tp_status & libc::TP_STATUS_VLAN_VALID != 0).then_some(hdr.hv1.tp_vlan_tci as u16)
+    }
+
+    /// The link-layer metadata stored after the frame header
+    pub fn info(&self) -> PacketInfo {
+        unsafe {
+            let addr = self.base.add(libc::TPACKET_ALIGN(std::mem::size_of::<libc::tpacket3_hdr>()));
+            PacketInfo::from_sockaddr(&*(addr as *const libc::sockaddr_ll), self.len()).with_timestamp(self.timestamp())
+        }
+    }
+}
+
+unsafe impl Send for RxFrame<'_> {}
+unsafe impl Sync for RxFrame<'_> {}
@@ -0,0 +1,265 @@
+use std::{ffi::c_int, future::poll_fn, io, os::fd::AsRawFd, sync::atomic::{AtomicU32, Ordering}};
+
+use crate::{sys, RawSock};
+use super::{invalid, page_size, Mmap};
+
+/// Offset of the frame data from the start of its `tpacket2_hdr` slot
+const DATA_OFFSET: usize = libc::TPACKET2_HDRLEN - std::mem::size_of::<libc::sockaddr_ll>();
+
+/// Geometry of a TPACKET_V2 transmit ring
+#[derive(Debug, Clone)]
+pub struct TxRingOpts {
+    frame_size: u32,
+    frame_count: u32,
+}
+
+impl Default for TxRingOpts {
+    fn default() -> Self {
+        Self {
+            frame_size: 2048,
+            frame_count: 1024,
+        }
+    }
+}
+
+impl TxRingOpts {
+    /// Size of each frame slot in bytes, including the frame header
+    pub fn frame_size(mut self, size: u32) -> Self {
+        self.frame_size = size;
+        self
+    }
+
+    /// Number of frames that can be queued before a flush
+    pub fn frame_count(mut self, count: u32) -> Self {
+        self.frame_count = count;
+        self
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        if self.frame_count == 0 {
+            return Err(invalid("ring frame count must be non-zero"));
+        }
+
+        if (self.frame_size as usize) <= DATA_OFFSET || !(self.frame_size as usize).is_multiple_of(libc::TPACKET_ALIGNMENT) {
+            return Err(invalid("ring frame size must be aligned and larger than the frame header"));
+        }
+
+        Ok(())
+    }
+}
+
+/// How a [`TxRing::flush`] went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FlushReport {
+    /// Frames handed to the device
+    pub sent: usize,
+    /// Frames the kernel rejected with `TP_STATUS_WRONG_FORMAT`, e.g. for exceeding the MTU
+    pub failed: usize,
+}
+
+/// A `PACKET_TX_RING` of TPACKET_V2 frames. Frames are queued into slots with [`TxRing::slot`]
+/// and all handed to the kernel by a single `send` in [`TxRing::flush`]
+pub struct TxRing {
+    map: Mmap,
+    sock: RawSock,
+    frame_size: usize,
+    frames_per_block: usize,
+    block_size: usize,
+    frame_count: usize,
+    /// The oldest queued frame, which is also where the kernel will resume sending
+    tail: usize,
+    queued: usize,
+}
+
+impl TxRing {
+    pub fn new(sock: RawSock, opts: TxRingOpts) -> io::Result<Self> {
+        opts.validate()?;
+
+        // Frames may not straddle blocks, so use the smallest page multiple that fits one
+        let frame_size = opts.frame_size as usize;
+        let block_size = frame_size.next_multiple_of(page_size());
+        let frames_per_block = block_size / frame_size;
+        let block_count = (opts.frame_count as usize).div_ceil(frames_per_block);
+        let frame_count = frames_per_block * block_count;
+
+        let fd = sock.fd.as_raw_fd();
+        let req = libc::tpacket_req {
+            tp_block_size: block_size as u32,
+            tp_block_nr: block_count as u32,
+            tp_frame_size: opts.frame_size,
+            tp_frame_nr: frame_count as u32,
+        };
+
+        sys::setsockopt(fd, libc::SOL_PACKET, libc::PACKET_VERSION, &(libc::tpacket_versions::TPACKET_V2 as c_int))?;
+        sys::setsockopt(fd, libc::SOL_PACKET, libc::PACKET_TX_RING, &req)?;
+
+        Ok(Self {
+            map: Mmap::new(fd, block_size * block_count)?,
+            sock,
+            frame_size,
+            frames_per_block,
+            block_size,
+            frame_count,
+            tail: 0,
+            queued: 0,
+        })
+    }
+
+    /// The largest frame a slot can hold
+    pub fn frame_capacity(&self) -> usize {
+        self.frame_size - DATA_OFFSET
+    }
+
+    /// Number of frames queued and not yet flushed
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// The next free slot, or `None` if every frame is queued and the ring needs a flush
+    pub fn slot(&mut self) -> Option<TxSlot<'_>> {
+        if self.queued == self.frame_count {
+            return None;
+        }
+
+        let index = (self.tail + self.queued) % self.frame_count;
+        Some(TxSlot { ring: self, index })
+    }
+
+    /// Copies `frame` into the next free slot and queues it. Returns `false` if the ring is full
+    ///
+    /// # Panics
+    ///
+    /// If `frame` is larger than [`TxRing::frame_capacity`]
+    pub fn push(&mut self, frame: &[u8]) -> bool {
+        match self.slot() {
+            Some(mut slot) => {
+                slot.buf()[..frame.len()].copy_from_slice(frame);
+                slot.commit(frame.len());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Asks the kernel to send every queued frame and waits until it has dealt with all of them
+    pub async fn flush(&mut self) -> io::Result<FlushReport> {
+        let mut report = FlushReport::default();
+
+        while self.queued > 0 {
+            self.send()?;
+            let failed = report.failed;
+            self.reap(&mut report);
+
+            // Frames moved up past a rejected one have not been looked at by the kernel yet
+            if self.queued == 0 || report.failed > failed {
+                continue;
+            }
+
+            // Still in flight, or left queued because the send buffer was full. Completions free
+            // it up and wake the socket as writable, and each wakeup sends whatever is still queued
+            poll_fn(|cx| self.sock.poll_write(cx, |_| {
+                self.send()?;
+                match self.status(self.tail).load(Ordering::Acquire) {
+                    libc::TP_STATUS_SENDING | libc::TP_STATUS_SEND_REQUEST => Err(io::ErrorKind::WouldBlock.into()),
+                    _ => Ok(()),
+                }
+            }))
+            .await?;
+        }
+
+        Ok(report)
+    }
+
+    fn send(&self) -> io::Result<()> {
+        let res = unsafe { libc::send(self.sock.fd.as_raw_fd(), std::ptr::null(), 0, 0) };
+
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            // Rejected frames are picked up from their status instead
+            if !matches!(err.raw_os_error(), Some(libc::EAGAIN | libc::EINVAL | libc::EMSGSIZE)) {
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reclaims finished frames from the tail, counting them into `report`
+    fn reap(&mut self, report: &mut FlushReport) {
+        while self.queued > 0 {
+            match self.status(self.tail).load(Ordering::Acquire) {
+                libc::TP_STATUS_AVAILABLE => {
+                    report.sent += 1;
+                    self.tail = (self.tail + 1) % self.frame_count;
+                }
+                libc::TP_STATUS_WRONG_FORMAT => {
+                    report.failed += 1;
+                    self.drop_tail();
+                }
+                _ => break,
+            }
+
+            self.queued -= 1;
+        }
+    }
+
+    /// The kernel stops at a malformed frame and resumes from that same slot, so the frames queued
+    /// behind it are moved up one slot each rather than skipping over it
+    fn drop_tail(&mut self) {
+        for i in 1..self.queued {
+            let (from, to) = ((self.tail + i) % self.frame_count, (self.tail + i - 1) % self.frame_count);
+
+            unsafe {
+                let len = (*self.frame_ptr(from)).tp_len;
+                let src = (self.frame_ptr(from) as *const u8).add(DATA_OFFSET);
+                let dst = (self.frame_ptr(to) as *mut u8).add(DATA_OFFSET);
+                std::ptr::copy_nonoverlapping(src, dst, len as usize);
+                (*self.frame_ptr(to)).tp_len = len;
+            }
+            self.status(to).store(libc::TP_STATUS_SEND_REQUEST, Ordering::Release);
+        }
+
+        let last = (self.tail + self.queued - 1) % self.frame_count;
+        self.status(last).store(libc::TP_STATUS_AVAILABLE, Ordering::Release);
+    }
+
+    fn frame_ptr(&self, index: usize) -> *mut libc::tpacket2_hdr {
+        let offset = (index / self.frames_per_block) * self.block_size + (index % self.frames_per_block) * self.frame_size;
+        unsafe { self.map.ptr.add(offset) as *mut libc::tpacket2_hdr }
+    }
+
+    fn status(&self, index: usize) -> &AtomicU32 {
+        unsafe { AtomicU32::from_ptr(&raw mut (*self.frame_ptr(index)).tp_status) }
+    }
+}
+
+/// A free frame slot in a [`TxRing`]. Nothing is queued unless [`TxSlot::commit`] is called
+pub struct TxSlot<'a> {
+    ring: &'a mut TxRing,
+    index: usize,
+}
+
+impl TxSlot<'_> {
+    /// The slot's frame buffer, [`TxRing::frame_capacity`] bytes long
+    pub fn buf(&mut self) -> &mut [u8] {
+        unsafe {
+            let data = (self.ring.frame_ptr(self.index) as *mut u8).add(DATA_OFFSET);
+            std::slice::from_raw_parts_mut(data, self.ring.frame_capacity())
+        }
+    }
+
+    /// Queues the first `len` bytes of the buffer as a frame, marking it `TP_STATUS_SEND_REQUEST`
+    ///
+    /// # Panics
+    ///
+    /// If `len` is larger than [`TxRing::frame_capacity`]
+    pub fn commit(self, len: usize) {
+        assert!(len <= self.ring.frame_capacity(), "frame of {len} bytes exceeds the ring's frame capacity");
+
+        unsafe {
+            (*self.ring.frame_ptr(self.index)).tp_len = len as u32;
+        }
+        self.ring.status(self.index).store(libc::TP_STATUS_SEND_REQUEST, Ordering::Release);
+        self.ring.queued += 1;
+    }
+}
@@ -0,0 +1,219 @@
+//! Halves of a [`RawSock`] for receiving and sending from separate tasks. Reads and writes
+//! already wait for readiness separately, so the halves never hold each other up
+
+use std::{error::Error, fmt, io, sync::Arc, task::{Context, Poll}};
+
+use crate::{LinkAddr, PacketBuf, PacketInfo, RawSock, TxTimestamps};
+
+/// The receiving methods of [`RawSock`], for a half that borrows or shares one
+macro_rules! recv_methods {
+    () => {
+        /// See [`RawSock::read`]
+        pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sock().read(buf).await
+        }
+
+        /// See [`RawSock::recv_from`]
+        pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, PacketInfo)> {
+            self.sock().recv_from(buf).await
+        }
+
+        /// See [`RawSock::read_exact_frame`]
+        pub async fn read_exact_frame(&self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sock().read_exact_frame(buf).await
+        }
+
+        /// See [`RawSock::read_batch`]
+        pub async fn read_batch(&self, bufs: &mut [PacketBuf]) -> io::Result<usize> {
+            self.sock().read_batch(bufs).await
+        }
+
+        /// See [`RawSock::poll_recv`]
+        pub fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            self.sock().poll_recv(cx, buf)
+        }
+
+        /// See [`RawSock::poll_recv_from`]
+        pub fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<(usize, PacketInfo)>> {
+            self.sock().poll_recv_from(cx, buf)
+        }
+
+        /// See [`RawSock::try_read`]
+        pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sock().try_read(buf)
+        }
+
+        /// See [`RawSock::try_recv_from`]
+        pub fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, PacketInfo)> {
+            self.sock().try_recv_from(buf)
+        }
+
+        /// See [`RawSock::readable`]
+        pub async fn readable(&self) -> io::Result<()> {
+            self.sock().readable().await
+        }
+    };
+}
+
+/// The sending methods of [`RawSock`], for a half that borrows or shares one
+macro_rules! send_methods {
+    () => {
+        /// See [`RawSock::write`]
+        pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+            self.sock().write(buf).await
+        }
+
+        /// See [`RawSock::send_to`]
+        pub async fn send_to(&self, buf: &[u8], addr: &LinkAddr) -> io::Result<usize> {
+            self.sock().send_to(buf, addr).await
+        }
+
+        /// See [`RawSock::write_batch`]
+        pub async fn write_batch(&self, frames: &[&[u8]]) -> io::Result<usize> {
+            self.sock().write_batch(frames).await
+        }
+
+        /// See [`RawSock::poll_send`]
+        pub fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.sock().poll_send(cx, buf)
+        }
+
+        /// See [`RawSock::poll_send_to`]
+        pub fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], addr: &LinkAddr) -> Poll<io::Result<usize>> {
+            self.sock().poll_send_to(cx, buf, addr)
+        }
+
+        /// See [`RawSock::try_write`]
+        pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+            self.sock().try_write(buf)
+        }
+
+        /// See [`RawSock::writable`]
+        pub async fn writable(&self) -> io::Result<()> {
+            self.sock().writable().await
+        }
+
+        /// See [`RawSock::tx_timestamps`]
+        pub fn tx_timestamps(&self) -> TxTimestamps<'_> {
+            self.sock().tx_timestamps()
+        }
+    };
+}
+
+/// The receiving half of a [`RawSock`], from [`RawSock::split`]
+#[derive(Debug, Clone, Copy)]
+pub struct ReadHalf<'a>(&'a RawSock);
+
+/// The sending half of a [`RawSock`], from [`RawSock::split`]
+#[derive(Debug, Clone, Copy)]
+pub struct WriteHalf<'a>(&'a RawSock);
+
+/// The receiving half of a [`RawSock`], from [`RawSock::into_split`]
+#[derive(Debug)]
+pub struct RawRecvHalf(Arc<RawSock>);
+
+/// The sending half of a [`RawSock`], from [`RawSock::into_split`]
+#[derive(Debug)]
+pub struct RawSendHalf(Arc<RawSock>);
+
+impl ReadHalf<'_> {
+    fn sock(&self) -> &RawSock {
+        self.0
+    }
+
+    recv_methods!();
+}
+
+impl WriteHalf<'_> {
+    fn sock(&self) -> &RawSock {
+        self.0
+    }
+
+    send_methods!();
+}
+
+impl RawRecvHalf {
+    fn sock(&self) -> &RawSock {
+        &self.0
+    }
+
+    /// Puts the socket back together, failing if `other` came from a different one
+    pub fn reunite(self, other: RawSendHalf) -> Result<RawSock, ReuniteError> {
+        reunite(self, other)
+    }
+
+    recv_methods!();
+}
+
+impl RawSendHalf {
+    fn sock(&self) -> &RawSock {
+        &self.0
+    }
+
+    /// Puts the socket back together, failing if `other` came from a different one
+    pub fn reunite(self, other: RawRecvHalf) -> Result<RawSock, ReuniteError> {
+        reunite(other, self)
+    }
+
+    send_methods!();
+}
+
+fn reunite(recv: RawRecvHalf, send: RawSendHalf) -> Result<RawSock, ReuniteError> {
+    if !Arc::ptr_eq(&recv.0, &send.0) {
+        return Err(ReuniteError(recv, send));
+    }
+
+    // The halves hold the only two references
+    drop(send);
+    Ok(Arc::into_inner(recv.0).expect("RawSock: a split half outlived its reunion"))
+}
+
+impl AsRef<RawSock> for ReadHalf<'_> {
+    fn as_ref(&self) -> &RawSock {
+        self.0
+    }
+}
+
+impl AsRef<RawSock> for WriteHalf<'_> {
+    fn as_ref(&self) -> &RawSock {
+        self.0
+    }
+}
+
+impl AsRef<RawSock> for RawRecvHalf {
+    fn as_ref(&self) -> &RawSock {
+        &self.0
+    }
+}
+
+impl AsRef<RawSock> for RawSendHalf {
+    fn as_ref(&self) -> &RawSock {
+        &self.0
+    }
+}
+
+/// The error from reuniting halves of two different sockets, handing both back
+#[derive(Debug)]
+pub struct ReuniteError(pub RawRecvHalf, pub RawSendHalf);
+
+impl fmt::Display for ReuniteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("tried to reunite halves that are not from the same socket")
+    }
+}
+
+impl Error for ReuniteError {}
+
+impl RawSock {
+    /// Splits the socket into halves borrowing it, one receiving and one sending
+    pub fn split(&self) -> (ReadHalf<'_>, WriteHalf<'_>) {
+        (ReadHalf(self), WriteHalf(self))
+    }
+
+    /// Splits the socket into halves that own it together, e.g. to move into separate spawned
+    /// tasks. [`RawRecvHalf::reunite`] puts it back together
+    pub fn into_split(self) -> (RawRecvHalf, RawSendHalf) {
+        let sock = Arc::new(self);
+        (RawRecvHalf(sock.clone()), RawSendHalf(sock))
+    }
+}
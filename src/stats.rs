@@ -0,0 +1,176 @@
+use std::{collections::HashMap, io, mem, os::fd::AsRawFd, sync::{atomic::{AtomicU64, Ordering}, Mutex}};
+
+use crate::{PacketInfo, RawSock};
+
+/// The kernel's counters for a socket (`PACKET_STATISTICS`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PacketStats {
+    packets: u32,
+    drops: u32,
+    freeze_q_cnt: Option<u32>,
+}
+
+impl PacketStats {
+    /// Frames that reached the socket, including the dropped ones
+    pub fn packets(&self) -> u32 {
+        self.packets
+    }
+
+    /// Frames dropped for lack of room in the receive buffer or ring
+    pub fn drops(&self) -> u32 {
+        self.drops
+    }
+
+    /// Times the receive ring was frozen because userspace held on to every block. Only
+    /// TPACKET_V3 rings report this
+    pub fn freeze_q_cnt(&self) -> Option<u32> {
+        self.freeze_q_cnt
+    }
+}
+
+/// A snapshot of what a socket has moved through its own read and write calls. Frames going
+/// through rings are not counted
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IoStats {
+    rx_packets: u64,
+    rx_bytes: u64,
+    tx_packets: u64,
+    tx_bytes: u64,
+    errors: HashMap<io::ErrorKind, u64>,
+}
+
+impl IoStats {
+    pub fn rx_packets(&self) -> u64 {
+        self.rx_packets
+    }
+
+    pub fn rx_bytes(&self) -> u64 {
+        self.rx_bytes
+    }
+
+    pub fn tx_packets(&self) -> u64 {
+        self.tx_packets
+    }
+
+    pub fn tx_bytes(&self) -> u64 {
+        self.tx_bytes
+    }
+
+    /// How many calls failed with an error of `kind`
+    pub fn errors(&self, kind: io::ErrorKind) -> u64 {
+        self.errors.get(&kind).copied().unwrap_or(0)
+    }
+
+    /// Every call that failed, whatever the error
+    pub fn total_errors(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    errors: Mutex<HashMap<io::ErrorKind, u64>>,
+}
+
+impl Counters {
+    /// Counts the outcome of a read, passing it through
+    pub(crate) fn rx(&self, res: io::Result<usize>) -> io::Result<usize> {
+        match &res {
+            Ok(len) => self.received(*len),
+            Err(err) => self.record_error(err),
+        }
+        res
+    }
+
+    /// Counts the outcome of a write, passing it through
+    pub(crate) fn tx(&self, res: io::Result<usize>) -> io::Result<usize> {
+        match &res {
+            Ok(len) => self.sent(*len),
+            Err(err) => self.record_error(err),
+        }
+        res
+    }
+
+    /// Counts the outcome of a read that also returned the frame's metadata, passing it through
+    pub(crate) fn rx_info(&self, res: io::Result<(usize, PacketInfo)>) -> io::Result<(usize, PacketInfo)> {
+        match &res {
+            Ok((len, _)) => self.received(*len),
+            Err(err) => self.record_error(err),
+        }
+        res
+    }
+
+    pub(crate) fn received(&self, len: usize) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sent(&self, len: usize) {
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+        self.tx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Counts an error returned without a length, passing it through
+    pub(crate) fn error(&self, err: io::Error) -> io::Error {
+        self.record_error(&err);
+        err
+    }
+
+    /// `WouldBlock` from the non-blocking calls is part of how they work, so it is not counted
+    fn record_error(&self, err: &io::Error) {
+        if err.kind() == io::ErrorKind::WouldBlock {
+            return;
+        }
+        *self.errors.lock().unwrap().entry(err.kind()).or_default() += 1;
+    }
+
+    fn snapshot(&self) -> IoStats {
+        IoStats {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            errors: self.errors.lock().unwrap().clone(),
+        }
+    }
+}
+
+impl RawSock {
+    /// Reads the kernel's counters for the socket. Reading them resets them, so each call
+    /// reports what happened since the last one
+    pub fn stats(&self) -> io::Result<PacketStats> {
+        let mut stats: libc::tpacket_stats_v3 = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::tpacket_stats_v3>() as libc::socklen_t;
+
+        let res = unsafe {
+            libc::getsockopt(
+                self.fd.as_raw_fd(),
+                libc::SOL_PACKET,
+                libc::PACKET_STATISTICS,
+                &mut stats as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        // Sockets without a TPACKET_V3 ring only fill in the leading `tpacket_stats`
+        let v3 = len as usize == mem::size_of::<libc::tpacket_stats_v3>();
+        Ok(PacketStats {
+            packets: stats.tp_packets,
+            drops: stats.tp_drops,
+            freeze_q_cnt: v3.then_some(stats.tp_freeze_q_cnt),
+        })
+    }
+
+    /// The bytes and frames this socket has read and written, and the errors it has returned
+    pub fn io_stats(&self) -> IoStats {
+        self.counters.snapshot()
+    }
+}
@@ -0,0 +1,187 @@
+use std::{borrow::Borrow, collections::VecDeque, io, ops::Deref, pin::Pin, task::{ready, Context, Poll}};
+
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use crate::{PacketInfo, RawSock};
+
+/// Read buffer size for sockets whose interface MTU is unknown, e.g. unbound ones
+pub(crate) const DEFAULT_CAPACITY: usize = 65536 + 18;
+
+/// How much is allocated at once for frames to be received into
+const POOL_SIZE: usize = 256 * 1024;
+
+/// A received frame, owning its bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    data: Bytes,
+    info: PacketInfo,
+}
+
+impl Frame {
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    pub fn info(&self) -> &PacketInfo {
+        &self.info
+    }
+
+    pub fn into_parts(self) -> (Bytes, PacketInfo) {
+        (self.data, self.info)
+    }
+}
+
+impl Deref for Frame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Receives a frame into the front of `pool` and splits it off. Frames are cut back to back out
+/// of one allocation, and a new one is only made once there is no room left for `capacity` bytes,
+/// so a frame kept around keeps the whole allocation it was cut from alive
+pub(crate) fn poll_recv_frame(sock: &RawSock, cx: &mut Context<'_>, pool: &mut BytesMut, capacity: usize) -> Poll<io::Result<(BytesMut, PacketInfo)>> {
+    if pool.capacity() < capacity {
+        pool.reserve(POOL_SIZE.max(capacity));
+    }
+    // What is left after the previous frame is already zeroed, so this only tops it up
+    if pool.len() < capacity {
+        pool.resize(capacity, 0);
+    }
+
+    let (len, info) = ready!(sock.poll_recv_from(cx, &mut pool[..capacity]))?;
+    Poll::Ready(Ok((pool.split_to(len), info)))
+}
+
+/// The frames received on a socket as a [`Stream`], from [`RawSock::frames`]. It never ends;
+/// read errors are yielded and reading carries on after them.
+///
+/// Frames are cut out of shared allocations, each freed once every frame cut from it is dropped.
+/// The socket can be owned, or shared as a `&RawSock` or `Arc<RawSock>`
+#[derive(Debug)]
+pub struct Frames<T = RawSock> {
+    sock: T,
+    pool: BytesMut,
+    capacity: usize,
+}
+
+impl<T: Borrow<RawSock>> Frames<T> {
+    pub fn new(sock: T) -> Self {
+        let capacity = sock.borrow().frame_capacity().unwrap_or(DEFAULT_CAPACITY);
+        Self { sock, pool: BytesMut::new(), capacity }
+    }
+
+    /// Sets the most bytes of each frame kept, by default [`RawSock::frame_capacity`]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.sock
+    }
+
+    pub fn into_inner(self) -> T {
+        self.sock
+    }
+}
+
+// Nothing is pinned structurally: the socket and pool are moved around freely
+impl<T> Unpin for Frames<T> {}
+
+impl<T: Borrow<RawSock>> Stream for Frames<T> {
+    type Item = io::Result<Frame>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let res = ready!(poll_recv_frame(this.sock.borrow(), cx, &mut this.pool, this.capacity));
+        Poll::Ready(Some(res.map(|(data, info)| Frame { data: data.freeze(), info })))
+    }
+}
+
+/// A [`Sink`] of whole frames sent on a socket, from [`RawSock::sink`]. Frames are queued up
+/// to the buffer size before being sent, and [`Sink::poll_flush`] sends the rest. A frame the
+/// kernel refuses is dropped from the queue and its error returned. Like [`Frames`], the socket
+/// can be owned or shared
+#[derive(Debug)]
+pub struct FrameSink<T = RawSock> {
+    sock: T,
+    queue: VecDeque<Bytes>,
+    buffer: usize,
+}
+
+impl<T: Borrow<RawSock>> FrameSink<T> {
+    pub fn new(sock: T) -> Self {
+        Self { sock, queue: VecDeque::new(), buffer: 1 }
+    }
+
+    /// Sets how many frames are queued before sending starts, by default 1 so that each frame is
+    /// sent as soon as the next one is offered
+    pub fn buffer(mut self, frames: usize) -> Self {
+        self.buffer = frames.max(1);
+        self
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.sock
+    }
+
+    /// The socket, dropping any frames still queued
+    pub fn into_inner(self) -> T {
+        self.sock
+    }
+
+    /// Sends queued frames until no more than `keep` are left
+    fn poll_send_queued(&mut self, cx: &mut Context<'_>, keep: usize) -> Poll<io::Result<()>> {
+        while self.queue.len() > keep {
+            let res = ready!(self.sock.borrow().poll_send(cx, &self.queue[0]));
+            self.queue.pop_front();
+            res?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Nor is anything here
+impl<T> Unpin for FrameSink<T> {}
+
+impl<T: Borrow<RawSock>> Sink<Bytes> for FrameSink<T> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let keep = this.buffer - 1;
+        this.poll_send_queued(cx, keep)
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: Bytes) -> io::Result<()> {
+        self.get_mut().queue.push_back(frame);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_queued(cx, 0)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl RawSock {
+    /// The frames received on this socket, as a [`Stream`] of owned [`Frame`]s. [`Frames::new`]
+    /// takes the socket by value instead
+    pub fn frames(&self) -> Frames<&RawSock> {
+        Frames::new(self)
+    }
+
+    /// A [`Sink`] sending frames on this socket like [`RawSock::write`]
+    pub fn sink(&self) -> FrameSink<&RawSock> {
+        FrameSink::new(self)
+    }
+}
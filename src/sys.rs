@@ -0,0 +1,232 @@
+use std::{ffi::{c_int, c_uint}, io, mem, os::fd::RawFd};
+
+pub(crate) fn setsockopt<T>(fd: RawFd, level: c_int, name: c_int, value: &T) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+
+    if res < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(())
+}
+
+pub(crate) fn getsockopt<T: Default>(fd: RawFd, level: c_int, name: c_int) -> io::Result<T> {
+    let mut value = T::default();
+    let mut len = mem::size_of::<T>() as libc::socklen_t;
+
+    if unsafe { libc::getsockopt(fd, level, name, &mut value as *mut T as *mut libc::c_void, &mut len) } < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(value)
+}
+
+pub(crate) fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(())
+}
+
+pub(crate) fn packet_membership(fd: RawFd, op: c_int, ifindex: c_int, mr_type: c_int, addr: &[u8]) -> io::Result<()> {
+    let mut mreq = libc::packet_mreq {
+        mr_ifindex: ifindex,
+        mr_type: mr_type as u16,
+        mr_alen: addr.len() as u16,
+        mr_address: [0; 8],
+    };
+    mreq.mr_address[..addr.len()].copy_from_slice(addr);
+
+    setsockopt(fd, libc::SOL_PACKET, op, &mreq)
+}
+
+/// The address a packet socket is bound to
+pub(crate) fn local_addr(fd: RawFd) -> io::Result<libc::sockaddr_ll> {
+    let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+
+    if unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) } < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(addr)
+}
+
+/// The source address and control messages of a message from [`recvmsg`]
+pub(crate) struct MsgMeta {
+    pub(crate) addr: libc::sockaddr_ll,
+    pub(crate) flags: c_int,
+    /// Room for every control message the socket options can turn on, aligned for `cmsghdr`
+    control: [u64; 32],
+    control_len: usize,
+}
+
+impl MsgMeta {
+    /// The `(level, type, data)` of each control message
+    pub(crate) fn cmsgs(&self) -> impl Iterator<Item = (c_int, c_int, &[u8])> {
+        let control = unsafe { std::slice::from_raw_parts(self.control.as_ptr() as *const u8, self.control_len) };
+        let mut offset = 0;
+
+        std::iter::from_fn(move || {
+            let hdr_len = mem::size_of::<libc::cmsghdr>();
+            let hdr = unsafe { (control.get(offset..offset + hdr_len)?.as_ptr() as *const libc::cmsghdr).read_unaligned() };
+            let data = control.get(offset + hdr_len..offset + hdr.cmsg_len as usize)?;
+
+            offset += (hdr.cmsg_len as usize).next_multiple_of(mem::size_of::<usize>());
+            Some((hdr.cmsg_level, hdr.cmsg_type, data))
+        })
+    }
+}
+
+impl MsgMeta {
+    fn new() -> Self {
+        Self {
+            addr: unsafe { mem::zeroed() },
+            flags: 0,
+            control: [0; 32],
+            control_len: 0,
+        }
+    }
+
+    /// A `msghdr` receiving into `iov` and this metadata, which must both stay put until the call
+    fn msghdr(&mut self, iov: &mut libc::iovec) -> libc::msghdr {
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut self.addr as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        msg.msg_iov = iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = self.control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&self.control);
+        msg
+    }
+
+    fn filled(&mut self, msg: &libc::msghdr) {
+        self.flags = msg.msg_flags;
+        self.control_len = msg.msg_controllen;
+    }
+}
+
+fn iovec(buf: &[u8]) -> libc::iovec {
+    libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    }
+}
+
+pub(crate) fn recv(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let res = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+    if res < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(res as usize)
+}
+
+pub(crate) fn send(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    let res = unsafe { libc::send(fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+    if res < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(res as usize)
+}
+
+pub(crate) fn sendto(fd: RawFd, buf: &[u8], addr: &libc::sockaddr_ll) -> io::Result<usize> {
+    let res = unsafe {
+        libc::sendto(
+            fd,
+            buf.as_ptr() as *const libc::c_void,
+            buf.len(),
+            0,
+            addr as *const _ as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(res as usize)
+}
+
+pub(crate) fn recvmsg(fd: RawFd, buf: &mut [u8], flags: c_int) -> io::Result<(usize, MsgMeta)> {
+    let mut meta = MsgMeta::new();
+    let mut iov = iovec(buf);
+    let mut msg = meta.msghdr(&mut iov);
+
+    let res = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+    if res < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    meta.filled(&msg);
+    Ok((res as usize, meta))
+}
+
+/// Receives up to one message into each of `bufs` (`recvmmsg`), returning the length and
+/// metadata of each message received
+pub(crate) fn recvmmsg(fd: RawFd, bufs: &mut [&mut [u8]], flags: c_int) -> io::Result<Vec<(usize, MsgMeta)>> {
+    let mut metas: Vec<_> = bufs.iter().map(|_| MsgMeta::new()).collect();
+    let mut iovs: Vec<_> = bufs.iter_mut().map(|buf| iovec(buf)).collect();
+    let mut msgs: Vec<_> = metas
+        .iter_mut()
+        .zip(&mut iovs)
+        .map(|(meta, iov)| libc::mmsghdr { msg_hdr: meta.msghdr(iov), msg_len: 0 })
+        .collect();
+
+    let res = unsafe { libc::recvmmsg(fd, msgs.as_mut_ptr(), msgs.len() as c_uint, flags, std::ptr::null_mut()) };
+    if res < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    let received = msgs.iter().take(res as usize).zip(metas).map(|(msg, mut meta)| {
+        meta.filled(&msg.msg_hdr);
+        (msg.msg_len as usize, meta)
+    });
+    Ok(received.collect())
+}
+
+/// Sends each of `frames` as its own message (`sendmmsg`), returning how many were sent
+pub(crate) fn sendmmsg(fd: RawFd, frames: &[&[u8]]) -> io::Result<usize> {
+    let mut iovs: Vec<_> = frames.iter().map(|frame| iovec(frame)).collect();
+    let mut msgs: Vec<_> = iovs
+        .iter_mut()
+        .map(|iov| {
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = iov;
+            msg.msg_iovlen = 1;
+            libc::mmsghdr { msg_hdr: msg, msg_len: 0 }
+        })
+        .collect();
+
+    let res = unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as c_uint, 0) };
+    if res < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(res as usize)
+}
+
+/// The MTU of the interface with index `ifindex` (`SIOCGIFMTU`), asked through any socket `fd`
+pub(crate) fn mtu(fd: RawFd, ifindex: c_int) -> io::Result<usize> {
+    let mut req: libc::ifreq = unsafe { mem::zeroed() };
+    if unsafe { libc::if_indextoname(ifindex as c_uint, req.ifr_name.as_mut_ptr()) }.is_null() {
+        return Err(io::Error::last_os_error())
+    }
+
+    if unsafe { libc::ioctl(fd, libc::SIOCGIFMTU, &mut req) } < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    Ok(unsafe { req.ifr_ifru.ifru_mtu } as usize)
+}
@@ -0,0 +1,97 @@
+use std::{future::poll_fn, io, pin::Pin, task::{ready, Context, Poll}, time::SystemTime};
+
+use futures_core::Stream;
+
+use crate::{packet, sys::{self, MsgMeta}, RawSock};
+
+/// When a sent frame was handed to the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TxTimestamp {
+    id: u32,
+    time: SystemTime,
+}
+
+impl TxTimestamp {
+    /// Which frame this is: the socket numbers every frame it sends from 0 (`SOF_TIMESTAMPING_OPT_ID`),
+    /// so the first [`RawSock::write`] or [`RawSock::send_to`] is 0, the next 1, and so on
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    fn from_msg(meta: &MsgMeta) -> Option<Self> {
+        let mut time = None;
+        let mut id = None;
+
+        for (level, ty, data) in meta.cmsgs() {
+            match (level, ty) {
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING) => time = packet::timestamp(data),
+                (libc::SOL_PACKET, libc::PACKET_TX_TIMESTAMP) => {
+                    let err = data.get(..std::mem::size_of::<libc::sock_extended_err>())?;
+                    let err = unsafe { (err.as_ptr() as *const libc::sock_extended_err).read_unaligned() };
+                    if err.ee_errno == libc::ENOMSG as u32 && err.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING {
+                        id = Some(err.ee_data);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(Self { id: id?, time: time? })
+    }
+}
+
+/// The transmit timestamps of a socket opened with
+/// [`SockOptsBuilder::tx_timestamps`](crate::SockOptsBuilder::tx_timestamps), from [`RawSock::tx_timestamps`].
+/// As a [`Stream`] it never ends
+pub struct TxTimestamps<'a> {
+    sock: &'a RawSock,
+    /// tokio has no way to poll for error readiness, only to wait for it, so the wait is kept here
+    /// between polls
+    pending: Option<Pin<Box<dyn Future<Output = io::Result<TxTimestamp>> + Send + 'a>>>,
+}
+
+impl TxTimestamps<'_> {
+    /// Waits for the next timestamp on the socket's error queue (`MSG_ERRQUEUE`). Timestamps
+    /// arrive in the order the frames were sent, and wait in the queue until read, counting
+    /// against the socket's receive buffer
+    pub async fn next(&mut self) -> io::Result<TxTimestamp> {
+        poll_fn(|cx| self.poll_timestamp(cx)).await
+    }
+
+    fn poll_timestamp(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<TxTimestamp>> {
+        let sock = self.sock;
+        // Anything else on the error queue is passed over
+        let pending = self.pending.get_or_insert_with(|| {
+            Box::pin(sock.fd.error_io(|fd| loop {
+                let (_, meta) = sys::recvmsg(fd, &mut [], libc::MSG_ERRQUEUE)?;
+                if let Some(timestamp) = TxTimestamp::from_msg(&meta) {
+                    return Ok(timestamp);
+                }
+            }))
+        });
+
+        let res = ready!(pending.as_mut().poll(cx));
+        self.pending = None;
+        Poll::Ready(res)
+    }
+}
+
+impl Stream for TxTimestamps<'_> {
+    type Item = io::Result<TxTimestamp>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_timestamp(cx).map(Some)
+    }
+}
+
+impl RawSock {
+    /// The transmit timestamps of frames sent on this socket, tied back to each frame by
+    /// [`TxTimestamp::id`]. Needs [`SockOptsBuilder::tx_timestamps`](crate::SockOptsBuilder::tx_timestamps)
+    pub fn tx_timestamps(&self) -> TxTimestamps<'_> {
+        TxTimestamps { sock: self, pending: None }
+    }
+}